@@ -0,0 +1,92 @@
+use crate::raster;
+
+use std::collections::HashMap;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+use tetra::graphics::Texture;
+use tetra::Context;
+
+/// How often files are checked for changes on disk.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Textures shared by path. A texture is loaded once no matter how many
+/// tokens use it and is reloaded when its file changes on disk.
+pub struct Assets {
+    textures: HashMap<String, Entry>,
+    errors: Vec<String>,
+    last_poll: Instant,
+}
+
+struct Entry {
+    texture: Option<Texture>, // None if the file couldn't be loaded
+    modified: Option<SystemTime>,
+}
+
+impl Assets {
+    pub fn new() -> Assets {
+        Assets {
+            textures: HashMap::new(),
+            errors: vec![],
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// The texture for `path`, loading it on first use. `None` if the file
+    /// is missing or broken; it is tried again once it changes on disk.
+    pub fn texture(&mut self, ctx: &mut Context, path: &str) -> tetra::Result<Option<Texture>> {
+        if let Some(entry) = self.textures.get(path) {
+            return Ok(entry.texture.clone());
+        }
+        let entry = self.load(ctx, path)?;
+        let texture = entry.texture.clone();
+        self.textures.insert(path.to_owned(), entry);
+        Ok(texture)
+    }
+
+    /// Reload every texture whose file changed since it was loaded. Returns
+    /// the paths that were reloaded.
+    pub fn reload_changed(&mut self, ctx: &mut Context) -> tetra::Result<Vec<String>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Ok(vec![]);
+        }
+        self.last_poll = Instant::now();
+
+        let changed: Vec<String> = self
+            .textures
+            .iter()
+            .filter(|(path, entry)| modified(path) != entry.modified)
+            .map(|(path, _)| path.to_owned())
+            .collect();
+        for path in &changed {
+            let entry = self.load(ctx, path)?;
+            self.textures.insert(path.to_owned(), entry);
+        }
+        Ok(changed)
+    }
+
+    /// Assets that failed to load since the last call.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn load(&mut self, ctx: &mut Context, path: &str) -> tetra::Result<Entry> {
+        let modified = modified(path);
+        let texture = match raster::load_image(path) {
+            Ok(img) => Some(Texture::from_rgba(
+                ctx,
+                img.width() as i32,
+                img.height() as i32,
+                &img,
+            )?),
+            Err(e) => {
+                self.errors.push(e);
+                None
+            }
+        };
+        Ok(Entry { texture, modified })
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,134 @@
+use crate::commands::{self, Command};
+use crate::domain::Model;
+use crate::gamefile;
+
+use std::path::Path;
+
+/// Parse and simulate a whole game file without opening a window and return
+/// every problem found, prefixed with its file and line number.
+pub fn check(path: &Path) -> Vec<String> {
+    let mut problems = vec![];
+    let mut model = Model::default();
+
+    for line in gamefile::load(path) {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                problems.push(e);
+                continue;
+            }
+        };
+        let location = match &line.location {
+            Some(l) => l.to_owned(),
+            None => continue,
+        };
+        let base_dir = location.file.parent().unwrap_or_else(|| Path::new("")).to_owned();
+        let mut report = |msg: String| problems.push(format!("{}: {}", location, msg));
+
+        for cmd in commands::parse(line.text) {
+            let mut cmd = match cmd {
+                Ok(c) => c,
+                Err(e) => {
+                    report(e);
+                    continue;
+                }
+            };
+            cmd.resolve_paths(&base_dir);
+            if let Err(e) = cmd.measure_image(&model.battlemap) {
+                report(e);
+                continue;
+            }
+            match &cmd {
+                Command::UpdateBattlemap(opts) => {
+                    if let Some(url) = &opts.url {
+                        check_image(url, &mut report);
+                    }
+                }
+                Command::UpdateToken(opts) => {
+                    if let Some(image) = &opts.image {
+                        check_image(image, &mut report);
+                    }
+                }
+                Command::Role(roller) if roller.roll().is_err() => {
+                    report(format!("Can't roll this: {:?}", roller));
+                }
+                _ => {}
+            }
+            match model.apply(&cmd) {
+                // what was asked for is no problem
+                Ok(notes) => notes
+                    .iter()
+                    .filter(|n| n.is_warning())
+                    .for_each(|n| report(n.to_string())),
+                Err(e) => report(e),
+            }
+        }
+    }
+    problems
+}
+
+fn check_image<F: FnMut(String)>(path: &str, report: &mut F) {
+    if !Path::new(path).is_file() {
+        report(format!("image {} doesn't exist", path));
+    }
+}
+
+#[test]
+fn test_check() {
+    let dir = std::env::temp_dir().join(format!("dnd-token-pusher-check-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let game = dir.join("broken.game");
+    std::fs::write(
+        &game,
+        "battlemap --columns=5 --rows=5\n\
+         token goblinking --name=Goblin --pos=A1\n\
+         # typo in the id\n\
+         token goblinkng --pos=B2\n\
+         token goblinking --pos=F1 --image=./does/not/exist.png\n\
+         tokn barb\n\
+         token barb --pos=E5\n\
+         battlemap --columns=4\n",
+    )
+    .unwrap();
+
+    let problems = check(&game);
+    let g = game.display();
+    assert_eq!(
+        vec![
+            format!("{}:4: Created new token 'goblinkng'. Did you mean 'goblinking'?", g),
+            format!("{}:5: image {} doesn't exist", g, dir.join("./does/not/exist.png").display()),
+            format!("{}:5: token goblinking: F1 is outside of the 5x5 battlemap", g),
+            format!("{}:6: Unknown command: tokn\n\tDid you mean 'token'?\n(type 'help' for a list of commands)", g),
+            format!("{}:8: token barb no longer fits: E5 is outside of the 4x5 battlemap", g),
+        ],
+        problems
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_ignores_info() {
+    let dir = std::env::temp_dir().join(format!("dnd-token-pusher-check-info-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let game = dir.join("fine.game");
+    std::fs::write(
+        &game,
+        "battlemap --columns=5 --rows=5\n\
+         region hall A1:B2\n\
+         token bob --pos=A1\n\
+         next\n\
+         distance bob E5\n",
+    )
+    .unwrap();
+
+    assert_eq!(Vec::<String>::new(), check(&game));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_check_example_game() {
+    let game = Path::new(env!("CARGO_MANIFEST_DIR")).join("example.game");
+    assert_eq!(Vec::<String>::new(), check(&game));
+}
@@ -1,6 +1,8 @@
+use crate::domain::FEET_PER_CELL;
+
 use regex::Regex;
-use std::num::ParseIntError;
-use std::option::NoneError;
+use std::fmt;
+use std::str::FromStr;
 
 const ALPHABET: [char; 26] = [
     'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R', 'S',
@@ -8,43 +10,43 @@ const ALPHABET: [char; 26] = [
 ];
 const BASE: i32 = 26;
 
-#[derive(Debug)]
-struct ParseError {
+lazy_static! {
+    static ref CHESS_NOTATION_PARTS: Regex = Regex::new(r"^([A-Za-z]+)(\d+)$").unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
     err: String,
 }
 
-impl From<NoneError> for ParseError {
-    fn from(e: NoneError) -> Self {
-        ParseError {
-            err: "No match found.".into(),
-        }
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.err)
     }
 }
 
-impl From<ParseIntError> for ParseError {
-    fn from(e: ParseIntError) -> Self {
-        ParseError {
-            err: format!("{}", e),
-        }
+impl ParseError {
+    fn new<S: Into<String>>(err: S) -> Self {
+        ParseError { err: err.into() }
     }
 }
 
+/// Columns are numbered in bijective base 26: A..Z, AA..ZZ, AAA.. and so on,
+/// with any number of letters in any case. Rows start at 1.
 fn to_map_coordinates(chess_coords: &str) -> Result<(i32, i32), ParseError> {
-    lazy_static! {
-        static ref CHESS_NOTATION_PARTS: Regex = Regex::new(r"^([A-Z]+)(\d+)$").unwrap();
-    }
-    let caps = CHESS_NOTATION_PARTS.captures(chess_coords)?;
-    let row: i32 = caps.get(2)?.as_str().parse()?;
-    let col: i32 = caps
-        .get(1)?
-        .as_str()
-        .chars()
-        .rev()
-        .enumerate()
-        .fold(0 as i32, |agg, (i, c)| {
-            let p: i32 = ALPHABET.iter().position(|&x| x == c).unwrap() as i32 + 1;
-            agg + BASE.pow(i as u32) * p
-        });
+    let caps = CHESS_NOTATION_PARTS
+        .captures(chess_coords)
+        .ok_or_else(|| ParseError::new(format!("{} is not a coordinate like C4", chess_coords)))?;
+    let too_large = || ParseError::new(format!("{} is too far off the map", chess_coords));
+    let row: i32 = caps[2].parse().map_err(|_| too_large())?;
+    if row == 0 {
+        return Err(ParseError::new(format!("{}: rows start at 1", chess_coords)));
+    }
+    let col = caps[1].chars().try_fold(0i32, |agg, c| {
+        let p = ALPHABET.iter().position(|&x| x == c.to_ascii_uppercase()).unwrap() as i32 + 1;
+        agg.checked_mul(BASE).and_then(|agg| agg.checked_add(p))
+    });
+    let col = col.ok_or_else(too_large)?;
 
     Ok((col - 1, row - 1)) // back to starting at 0
 }
@@ -62,86 +64,256 @@ fn test_to_map_coordinates() {
     assert_eq!(to_map_coordinates("YZ1").ok(), Some((675, 0)));
     assert_eq!(to_map_coordinates("ZA1").ok(), Some((676, 0)));
     assert_eq!(to_map_coordinates("ZB1").ok(), Some((677, 0)));
+    assert_eq!(to_map_coordinates("AAA1").ok(), Some((702, 0)));
+    assert_eq!(to_map_coordinates("c4").ok(), Some((2, 3)));
+    assert_eq!(to_map_coordinates("aB3").ok(), Some((27, 2)));
 }
 
-// TODO: make prive so we have to use the public enums!
-pub fn from_map_coordinates(column: i32, row: i32) -> String {
-    if column >= (27 * BASE) {
-        panic!(
-            "parameter column out of range [0..{}): {}",
-            27 * BASE,
-            column
-        );
-    }
-    let first_c: usize = (column / BASE) as usize;
-    let second_c: usize = (column % BASE) as usize;
-
-    let s: String = format!(
-        "{}{}{}",
-        if column >= BASE {
-            ALPHABET[first_c - 1].into()
-        } else {
-            ' '
-        },
-        ALPHABET[second_c],
-        row + 1,
+#[test]
+fn test_to_map_coordinates_errors() {
+    assert!(to_map_coordinates("A0").is_err());
+    assert!(to_map_coordinates("4C").is_err());
+    assert!(to_map_coordinates("Ä1").is_err());
+    assert!(to_map_coordinates("A99999999999").is_err());
+    assert!(to_map_coordinates("ZZZZZZZZZZ1").is_err());
+    assert_eq!(
+        "C is not a coordinate like C4",
+        to_map_coordinates("C").unwrap_err().to_string()
     );
-    s.trim().to_string()
+}
+
+/// `None` for cells left of or above A1, and for the last column and row,
+/// their names wouldn't parse back.
+fn from_map_coordinates(column: i32, row: i32) -> Option<String> {
+    if column < 0 || row < 0 {
+        return None;
+    }
+    let (mut n, row) = (column.checked_add(1)?, row.checked_add(1)?);
+    let mut letters = vec![];
+    while n > 0 {
+        n -= 1;
+        letters.push(ALPHABET[(n % BASE) as usize]);
+        n /= BASE;
+    }
+    let column: String = letters.iter().rev().collect();
+    Some(format!("{}{}", column, row))
 }
 
 #[test]
 fn test_chess_coordinates() {
-    assert_eq!("A1", from_map_coordinates(0, 0));
-    assert_eq!("A5", from_map_coordinates(0, 4));
-    assert_eq!("B2", from_map_coordinates(1, 1));
-    assert_eq!("Z1", from_map_coordinates(25, 0));
-    assert_eq!("AA1", from_map_coordinates(26, 0));
-    assert_eq!("AB1", from_map_coordinates(27, 0));
-    assert_eq!("YZ1", from_map_coordinates(675, 0));
-    assert_eq!("ZA1", from_map_coordinates(676, 0));
-    assert_eq!("ZB1", from_map_coordinates(677, 0));
-    assert_eq!("ZZ1", from_map_coordinates(701, 0));
-    // from_map_coordinates(702, 0); // panic!
-}
-
-#[derive(Clone, PartialEq, Eq, Debug)]
+    let name = |col, row| from_map_coordinates(col, row).unwrap();
+    assert_eq!("A1", name(0, 0));
+    assert_eq!("A5", name(0, 4));
+    assert_eq!("B2", name(1, 1));
+    assert_eq!("Z1", name(25, 0));
+    assert_eq!("AA1", name(26, 0));
+    assert_eq!("AB1", name(27, 0));
+    assert_eq!("YZ1", name(675, 0));
+    assert_eq!("ZA1", name(676, 0));
+    assert_eq!("ZB1", name(677, 0));
+    assert_eq!("ZZ1", name(701, 0));
+    assert_eq!("AAA1", name(702, 0));
+    assert_eq!("FXSHRXW2147483647", name(i32::MAX - 1, i32::MAX - 1));
+    assert_eq!(None, from_map_coordinates(-1, 0));
+    assert_eq!(None, from_map_coordinates(0, -1));
+    assert_eq!(None, from_map_coordinates(i32::MAX, 0));
+    assert_eq!(None, from_map_coordinates(0, i32::MAX));
+    assert!(to_map_coordinates("FXSHRXX1").is_err());
+    assert!(to_map_coordinates("A2147483648").is_err());
+}
+
+#[test]
+fn test_round_trip() {
+    use quickcheck::{quickcheck, QuickCheck, StdGen, TestResult};
+    fn named(col: i32, row: i32) -> TestResult {
+        match from_map_coordinates(col, row) {
+            Some(name) => TestResult::from_bool(
+                to_map_coordinates(&name) == Ok((col, row))
+                    && to_map_coordinates(&name.to_lowercase()) == Ok((col, row)),
+            ),
+            None => TestResult::from_bool(col < 0 || row < 0 || col == i32::MAX || row == i32::MAX),
+        }
+    }
+    fn parsed(letters: Vec<u8>, row: u16) -> TestResult {
+        let letters: String = letters.iter().take(5).map(|&b| ALPHABET[b as usize % 26]).collect();
+        if letters.is_empty() || row == 0 {
+            return TestResult::discard();
+        }
+        let name = format!("{}{}", letters, row);
+        let (col, row) = to_map_coordinates(&name).unwrap();
+        TestResult::from_bool(from_map_coordinates(col, row) == Some(name))
+    }
+    // small cells, then all the way up to the last nameable one
+    quickcheck(named as fn(i32, i32) -> TestResult);
+    QuickCheck::new()
+        .gen(StdGen::new(rand::thread_rng(), i32::MAX as usize))
+        .quickcheck(named as fn(i32, i32) -> TestResult);
+    quickcheck(parsed as fn(Vec<u8>, u16) -> TestResult);
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub enum Coordinates {
     Chess(String),
     Map((i32, i32)),
+    Feet((f32, f32)), // free position from the top left corner of A1
+}
+
+/// Parse a position as typed: a cell like `C4` (in any case) or a free
+/// position in feet like `12.5ft,30ft`.
+impl FromStr for Coordinates {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref FEET: Regex = Regex::new(r"^(-?\d+(?:\.\d+)?)ft,(-?\d+(?:\.\d+)?)ft$").unwrap();
+        }
+        match FEET.captures(s) {
+            Some(caps) => {
+                let feet = |v: &str| v.parse().map_err(|e| ParseError::new(format!("{}: {}", s, e)));
+                Ok(Coordinates::Feet((feet(&caps[1])?, feet(&caps[2])?)))
+            }
+            None => {
+                to_map_coordinates(s)?;
+                Ok(Coordinates::Chess(s.to_uppercase()))
+            }
+        }
+    }
+}
+
+/// Cells as `C4`, free positions as `12.5ft,30ft`. Cells without a name are
+/// written as `(column, row)`, counting from 0.
+impl fmt::Display for Coordinates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Coordinates::Chess(s) => write!(f, "{}", s.to_uppercase()),
+            Coordinates::Map((col, row)) => match from_map_coordinates(*col, *row) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "({}, {})", col, row),
+            },
+            Coordinates::Feet((x, y)) => write!(f, "{}ft,{}ft", x, y),
+        }
+    }
 }
 
 impl Coordinates {
+    #[cfg(test)]
     pub fn to_map(&self) -> Result<Coordinates, ParseError> {
-        Ok(match self {
-            Coordinates::Map(x) => Coordinates::Map(*x),
-            Coordinates::Chess(s) => Coordinates::Map(to_map_coordinates(s.as_str())?),
-        })
+        Ok(Coordinates::Map(self.to_position()?))
     }
 
-    pub fn to_chess(&self) -> Coordinates {
+    #[cfg(test)]
+    pub fn to_chess(&self) -> Result<Coordinates, ParseError> {
+        let (col, row) = self.to_position()?;
+        from_map_coordinates(col, row)
+            .map(Coordinates::Chess)
+            .ok_or_else(|| ParseError::new(format!("{} has no name, it is off the map", self)))
+    }
+
+    pub fn to_position(&self) -> Result<(i32, i32), ParseError> {
         match self {
-            Coordinates::Chess(s) => Coordinates::Chess((*s).to_owned()),
-            Coordinates::Map((col, row)) => Coordinates::Chess(from_map_coordinates(*col, *row)),
+            Coordinates::Chess(s) => to_map_coordinates(s),
+            Coordinates::Map(pos) => Ok(*pos),
+            Coordinates::Feet((x, y)) => {
+                let cell = FEET_PER_CELL as f32;
+                Ok(((x / cell).floor() as i32, (y / cell).floor() as i32))
+            }
         }
     }
 
-    pub fn to_position(&self) -> (i32, i32) {
+    /// Where `offset` cells away from here is. Free positions move
+    /// `FEET_PER_CELL` per cell.
+    pub fn moved(&self, (dx, dy): (i32, i32)) -> Result<Coordinates, ParseError> {
         match self {
-            Coordinates::Chess(s) => {
-                let r = to_map_coordinates(s);
-                r.unwrap()
-            },
-            Coordinates::Map(pos) => *pos,
+            Coordinates::Feet((x, y)) => {
+                let cell = FEET_PER_CELL as f32;
+                Ok(Coordinates::Feet((x + dx as f32 * cell, y + dy as f32 * cell)))
+            }
+            _ => {
+                let (col, row) = self.to_position()?;
+                Ok(Coordinates::Map((col.saturating_add(dx), row.saturating_add(dy))))
+            }
         }
     }
+
+    /// Where this is in feet from the top left corner of A1. Cells are
+    /// measured from their centre.
+    pub fn to_feet(&self) -> Result<(f32, f32), ParseError> {
+        match self {
+            Coordinates::Feet(feet) => Ok(*feet),
+            _ => {
+                let (col, row) = self.to_position()?;
+                let cell = FEET_PER_CELL as f32;
+                Ok(((col as f32 + 0.5) * cell, (row as f32 + 0.5) * cell))
+            }
+        }
+    }
+}
+
+/// A move relative to a position, in cells: `+2,-1` is two columns right and
+/// one row up. Compass steps go one cell per letter, `NNE` is two rows up and
+/// one column right.
+pub fn offset(s: &str) -> Result<(i32, i32), ParseError> {
+    lazy_static! {
+        static ref OFFSET: Regex = Regex::new(r"^([+-]?\d+),([+-]?\d+)$").unwrap();
+        static ref COMPASS: Regex = Regex::new(r"^[NESWnesw]+$").unwrap();
+    }
+    if let Some(caps) = OFFSET.captures(s) {
+        let cells = |v: &str| v.parse().map_err(|_| ParseError::new(format!("{} is too far", s)));
+        return Ok((cells(&caps[1])?, cells(&caps[2])?));
+    }
+    if !COMPASS.is_match(s) {
+        return Err(ParseError::new(format!("{} is not a move like +2,-1 or NNE", s)));
+    }
+    Ok(s.chars().fold((0, 0), |(x, y), c| match c.to_ascii_uppercase() {
+        'N' => (x, y - 1),
+        'E' => (x + 1, y),
+        'S' => (x, y + 1),
+        _ => (x - 1, y),
+    }))
+}
+
+#[test]
+fn test_offset() {
+    assert_eq!(Ok((2, -1)), offset("+2,-1"));
+    assert_eq!(Ok((0, 3)), offset("0,3"));
+    assert_eq!(Ok((1, -2)), offset("NNE"));
+    assert_eq!(Ok((0, 0)), offset("nsew"));
+    assert!(offset("").is_err());
+    assert!(offset("NX").is_err());
+    assert!(offset("+2").is_err());
+    assert!(offset("1,99999999999").is_err());
+    assert_eq!(Ok(Coordinates::Map((3, 2))), Coordinates::Chess("B4".into()).moved((2, -1)));
+    assert_eq!(Ok(Coordinates::Feet((20.0, 10.0))), Coordinates::Feet((10.0, 15.0)).moved((2, -1)));
+}
+
+#[test]
+fn test_parse() {
+    assert_eq!(Ok(Coordinates::Feet((12.5, 30.0))), "12.5ft,30ft".parse());
+    assert_eq!(Ok(Coordinates::Chess("C4".into())), "c4".parse());
+    assert!("12ft".parse::<Coordinates>().is_err());
+    assert!("12ft,3px".parse::<Coordinates>().is_err());
+    assert_eq!(Ok((2, 6)), Coordinates::Feet((12.5, 30.0)).to_position());
+    assert_eq!(Ok(Coordinates::Chess("C7".into())), Coordinates::Feet((12.5, 30.0)).to_chess());
+    assert_eq!(Ok((12.5, 17.5)), Coordinates::Chess("C4".into()).to_feet());
+}
+
+#[test]
+fn test_display() {
+    for s in &["C4", "AAA12", "12.5ft,30ft"] {
+        assert_eq!(*s, s.parse::<Coordinates>().unwrap().to_string());
+    }
+    assert_eq!("B3", Coordinates::Map((1, 2)).to_string());
+    assert_eq!("(-1, 2)", Coordinates::Map((-1, 2)).to_string());
+    assert!(Coordinates::Map((-1, 2)).to_chess().is_err());
 }
 
 #[test]
 fn test_to_position() {
     assert_eq!(
-        (0, 0),
+        Ok((0, 0)),
         Coordinates::Chess("A1".to_string()).to_position()
     );
+    assert!(Coordinates::Chess("1A".to_string()).to_position().is_err());
 }
 
 #[test]
@@ -151,8 +323,8 @@ fn test_coordinates_conversion() {
         Coordinates::Chess("A1".to_string())
     );
     assert_eq!(
-        Coordinates::Chess("A1".to_string()),
-        Coordinates::Chess("A1".to_string()).to_chess()
+        Ok(Coordinates::Chess("A1".to_string())),
+        Coordinates::Chess("a1".to_string()).to_chess()
     );
     assert_eq!(
         Some(Coordinates::Map((0, 0))),
@@ -165,7 +337,66 @@ fn test_coordinates_conversion() {
         Coordinates::Map((0, 0)).to_map().ok()
     );
     assert_eq!(
-        Coordinates::Chess("A1".to_string()),
+        Ok(Coordinates::Chess("A1".to_string())),
         Coordinates::Map((0, 0)).to_chess()
     );
 }
+
+/// Many cells at once: a single cell `C4`, a rectangle `A1:D4` (any two
+/// opposite corners), a comma list `A1,B3:C5` of those, or the name of a
+/// region.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Cells {
+    Range((i32, i32), (i32, i32)),
+    List(Vec<Cells>),
+    Region(String),
+}
+
+/// Region names start with a letter and don't look like a coordinate.
+pub fn is_region_name(s: &str) -> bool {
+    lazy_static! {
+        static ref NAME: Regex = Regex::new(r"^[A-Za-z][\w-]*$").unwrap();
+    }
+    NAME.is_match(s) && !CHESS_NOTATION_PARTS.is_match(s)
+}
+
+impl FromStr for Cells {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(',') {
+            return Ok(Cells::List(s.split(',').map(str::parse).collect::<Result<_, _>>()?));
+        }
+        if let Some(colon) = s.find(':') {
+            let (from, to) = (&s[..colon], &s[colon + 1..]);
+            return Ok(Cells::Range(to_map_coordinates(from)?, to_map_coordinates(to)?));
+        }
+        if is_region_name(s) {
+            return Ok(Cells::Region(s.to_owned()));
+        }
+        let cell = to_map_coordinates(s)?;
+        Ok(Cells::Range(cell, cell))
+    }
+}
+
+#[test]
+fn test_parse_cells() {
+    assert_eq!(Ok(Cells::Range((2, 3), (2, 3))), "c4".parse());
+    assert_eq!(Ok(Cells::Range((0, 0), (3, 3))), "A1:D4".parse());
+    assert_eq!(
+        Ok(Cells::List(vec![
+            Cells::Range((0, 0), (0, 0)),
+            Cells::Range((3, 3), (1, 2)),
+            Cells::Region("throne-room".into()),
+        ])),
+        "A1,D4:B3,throne-room".parse()
+    );
+    assert!("A1:".parse::<Cells>().is_err());
+    assert!("A1,,B2".parse::<Cells>().is_err());
+    assert!("A0".parse::<Cells>().is_err());
+    assert!("A1:B2:C3".parse::<Cells>().is_err());
+    assert!(is_region_name("hall_2"));
+    assert!(!is_region_name("hall2"));
+    assert!(!is_region_name("A0"));
+    assert!(!is_region_name("2nd-hall"));
+}
@@ -1,9 +1,15 @@
+use crate::chess::{self, Cells};
+use crate::detect;
+use crate::domain::{self, Shape};
+
+use std::path::Path;
 use structopt::StructOpt;
 
 mod opts {
     use structopt::StructOpt;
 
     #[derive(StructOpt, Debug, PartialEq, Clone)]
+    #[structopt(name = "token", about = "create or update token")]
     pub struct Token {
         // positional argument
         pub token_id: String,
@@ -20,14 +26,79 @@ mod opts {
         #[structopt(long = "max-health")]
         pub max_health: Option<i32>,
 
+        /// A cell like C4, or @other-token to stand next to it
         #[structopt(long)]
         pub pos: Option<String>,
 
+        /// Relative to the current position: +2,-1 in columns and rows, or
+        /// compass steps like NNE
+        #[structopt(long = "move", conflicts_with = "pos", allow_hyphen_values = true)]
+        pub step: Option<String>,
+
         #[structopt(long)]
         pub initiative: Option<i32>,
+
+        /// Walking speed in feet per turn
+        #[structopt(long)]
+        pub speed: Option<i32>,
+        #[structopt(long = "fly-speed")]
+        pub fly_speed: Option<i32>,
+        #[structopt(long = "swim-speed")]
+        pub swim_speed: Option<i32>,
+        #[structopt(long = "climb-speed")]
+        pub climb_speed: Option<i32>,
+
+        /// Fly this move, which ignores difficult terrain
+        #[structopt(long, conflicts_with_all = &["swim", "climb"])]
+        pub fly: bool,
+        /// Swim this move, at double cost without a swim speed
+        #[structopt(long, conflicts_with = "climb")]
+        pub swim: bool,
+        /// Climb this move, at double cost without a climb speed
+        #[structopt(long)]
+        pub climb: bool,
+
+        /// Tokens on different sides are hostile to each other
+        #[structopt(long)]
+        pub side: Option<String>,
+
+        /// How far the token's melee attacks reach, in feet
+        #[structopt(long)]
+        pub reach: Option<i32>,
     }
 
     #[derive(StructOpt, Debug, PartialEq, Clone)]
+    #[structopt(name = "move", about = "move a token to a cell")]
+    pub struct Move {
+        pub token_id: String,
+        pub cell: String,
+
+        /// Walk the cheapest way around walls and hostile tokens instead of
+        /// going straight
+        #[structopt(long = "via-path")]
+        pub via_path: bool,
+
+        #[structopt(long, conflicts_with_all = &["swim", "climb"])]
+        pub fly: bool,
+        #[structopt(long, conflicts_with = "climb")]
+        pub swim: bool,
+        #[structopt(long)]
+        pub climb: bool,
+    }
+
+    #[derive(StructOpt, Debug, PartialEq, Clone)]
+    #[structopt(name = "attack", about = "check the cover of a token against an attacker")]
+    pub struct Attack {
+        pub attacker: String,
+        pub target: String,
+
+        /// Armor class of the target, the cover bonus is added to it
+        #[structopt(long)]
+        pub ac: Option<i32>,
+    }
+
+    #[derive(StructOpt, Debug, PartialEq, Clone)]
+    #[structopt(name = "battlemap", about = "update battlemap settings")]
     pub struct Battlemap {
         #[structopt(long)]
         pub url: Option<String>,
@@ -35,6 +106,39 @@ mod opts {
         pub columns: Option<i32>,
         #[structopt(long)]
         pub rows: Option<i32>,
+        /// Cell size in pixels, `70` or `70x60`. Columns and rows that are
+        /// left out are counted on the image.
+        #[structopt(long = "cell-size", parse(try_from_str = parse_cell_size))]
+        pub cell_size: Option<(u32, u32)>,
+        /// Where the grid starts on the image, in pixels
+        #[structopt(long = "offset-x", allow_hyphen_values = true)]
+        pub offset_x: Option<i32>,
+        #[structopt(long = "offset-y", allow_hyphen_values = true)]
+        pub offset_y: Option<i32>,
+        #[structopt(long, possible_values = &["square", "hex-pointy", "hex-flat", "none"])]
+        pub grid: Option<crate::domain::Shape>,
+        /// Measure cell size and offset on the grid printed on the image.
+        /// Explicit values win.
+        #[structopt(long = "detect-grid")]
+        pub detect_grid: bool,
+
+        /// Size of the image in pixels, see `Command::measure_image`
+        #[structopt(skip)]
+        pub image_size: Option<(u32, u32)>,
+        /// The grid found for --detect-grid, see `Command::measure_image`
+        #[structopt(skip)]
+        pub detected: Option<crate::detect::Detection>,
+    }
+
+    fn parse_cell_size(s: &str) -> Result<(u32, u32), String> {
+        let size = |s: &str| match s.parse() {
+            Ok(0) | Err(_) => Err(format!("invalid cell size {}, expected e.g. 70 or 70x60", s)),
+            Ok(n) => Ok(n),
+        };
+        match s.find('x') {
+            Some(i) => Ok((size(&s[..i])?, size(&s[i + 1..])?)),
+            None => size(s).map(|n| (n, n)),
+        }
     }
 
     #[test]
@@ -43,7 +147,14 @@ mod opts {
             Battlemap {
                 url: Some(String::from("./assets/background.jpg")),
                 columns: Some(100),
-                rows: Some(100)
+                rows: Some(100),
+                cell_size: None,
+                offset_x: None,
+                offset_y: None,
+                grid: None,
+                detect_grid: false,
+                image_size: None,
+                detected: None,
             },
             Battlemap::from_iter_safe(
                 "battlemap --url=./assets/background.jpg --columns=100 --rows=100"
@@ -52,6 +163,24 @@ mod opts {
             .unwrap()
         )
     }
+
+    #[test]
+    fn opts_battlemap_grid_test() {
+        let opts = Battlemap::from_iter_safe(
+            "battlemap --cell-size=70x60 --offset-x=12 --offset-y=-8".split_whitespace(),
+        )
+        .unwrap();
+        assert_eq!(Some((70, 60)), opts.cell_size);
+        assert_eq!((Some(12), Some(-8)), (opts.offset_x, opts.offset_y));
+        let opts = Battlemap::from_iter_safe("battlemap --grid=hex-flat".split_whitespace()).unwrap();
+        assert_eq!(Some("hex-flat".parse().unwrap()), opts.grid);
+        assert!(Battlemap::from_iter_safe("battlemap --grid=triangle".split_whitespace()).is_err());
+        let opts = Battlemap::from_iter_safe("battlemap --cell-size 70".split_whitespace()).unwrap();
+        assert_eq!(Some((70, 70)), opts.cell_size);
+        assert!(Battlemap::from_iter_safe("battlemap --cell-size=0".split_whitespace()).is_err());
+        assert!(Battlemap::from_iter_safe("battlemap --cell-size=70x".split_whitespace()).is_err());
+    }
+
     #[test]
     fn opts_token_test() {
         assert_eq!(
@@ -62,7 +191,17 @@ mod opts {
                 size: Some("small".into()),
                 max_health: Some(5),
                 pos: Some("A1".into()),
+                step: None,
                 initiative: Some(11),
+                speed: None,
+                fly_speed: None,
+                swim_speed: None,
+                climb_speed: None,
+                fly: false,
+                swim: false,
+                climb: false,
+                side: None,
+                reach: None,
             },
             Token::from_iter_safe(
                 "token goblinking --image=goblin.png --name=Goblin --size=small --max-health=5 --pos=A1 --initiative=11"
@@ -73,53 +212,518 @@ mod opts {
     }
 }
 
+/// The cells a fog command applies to.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Area {
+    All,
+    Cells(Cells),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Terrain {
+    Normal,
+    Difficult, // costs double to move through
+}
+
 #[derive(Debug)]
 pub enum Command {
     UpdateBattlemap(opts::Battlemap),
     UpdateToken(opts::Token),
-    PrintHelp(String),
+    Reveal(Area),
+    Shadow(Area),
+    NextTurn,
+    Distance(String, String), // cells or token ids
+    CheckCover(String, String), // attacker, target
+    Attack(opts::Attack),
+    Region(String, Cells),
+    SetTerrain(Terrain, Area),
+    Wall(bool, Cells, Cells), // add or remove, between neighbours of both sides
+    FindPath(String, String), // token, cell
+    MoveAlongPath(opts::Move),
+    Dash(Option<String>), // the token whose turn it is if `None`
+    Disengage(Option<String>),
+    Strict(bool),
+    Calibrate,
+    PrintHelp(Option<String>),
+    Include(String),
     Quit,
     Role(caith::Roller),
 }
 
+impl Command {
+    /// Make relative file paths relative to `base_dir` instead of the working
+    /// directory.
+    pub fn resolve_paths(&mut self, base_dir: &Path) {
+        let resolve = |path: &mut String| {
+            if Path::new(path.as_str()).is_relative() {
+                *path = base_dir.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        };
+        match self {
+            Command::UpdateBattlemap(opts) => opts.url.as_mut().map_or((), resolve),
+            Command::UpdateToken(opts) => opts.image.as_mut().map_or((), resolve),
+            Command::Include(path) => resolve(path),
+            _ => {}
+        }
+    }
+
+    /// Look at the image of a battlemap command, so the model doesn't have
+    /// to: its size to count missing columns and rows, and for --detect-grid
+    /// the grid printed on it. `current` is the battlemap being updated.
+    pub fn measure_image(&mut self, current: &domain::Battlemap) -> Result<(), String> {
+        let opts = match self {
+            Command::UpdateBattlemap(opts) => opts,
+            _ => return Ok(()),
+        };
+        let path = match opts.url.as_ref().or(current.image_path.as_ref()) {
+            Some(path) => path.to_owned(),
+            None if opts.detect_grid => return Err("--detect-grid needs a battlemap image, set one with --url".into()),
+            None => return Ok(()),
+        };
+        opts.image_size = image::image_dimensions(&path).ok();
+        // the model refuses other shapes
+        if opts.detect_grid && opts.grid.unwrap_or(current.shape) == Shape::Square {
+            let img = image::open(&path)
+                .map_err(|e| format!("Can't load image {}: {}", path, e))?
+                .to_luma8();
+            let found = detect::grid(&img)
+                .filter(|d| d.confidence >= detect::MIN_CONFIDENCE)
+                .ok_or_else(|| format!("no grid found on {}", path))?;
+            opts.detected = Some(found);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_resolve_paths() {
+    let mut cmds = parse(
+        "token bob --image=goblin.png\ntoken bob --image=/abs/goblin.png\ninclude ../room.game".into(),
+    );
+    let base = Path::new("/campaign/dungeon");
+    for cmd in cmds.iter_mut() {
+        cmd.as_mut().unwrap().resolve_paths(base);
+    }
+    match (&cmds[0], &cmds[1], &cmds[2]) {
+        (Ok(Command::UpdateToken(a)), Ok(Command::UpdateToken(b)), Ok(Command::Include(c))) => {
+            assert_eq!(Some("/campaign/dungeon/goblin.png".to_string()), a.image);
+            assert_eq!(Some("/abs/goblin.png".to_string()), b.image);
+            assert_eq!("/campaign/dungeon/../room.game", c);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
 pub const HELP: &str = "Commands:
 battlemap       -> update battlemap settings
 token           -> create or update token
+reveal A2:F19   -> reveal cells to the players (also A1,C3 or a region, all if omitted)
+shadow A2:F19   -> hide cells from the players (also A1,C3 or a region, all if omitted)
+region x A1:F6  -> name cells, one cell regions also work for move, path and distance
+terrain x A1:C3 -> mark cells as x = difficult or normal terrain, all if omitted
+wall add A1 B1  -> block moves between neighbouring cells, e.g. C1:C5 D1:D5 (or remove)
+path bob E5     -> the cheapest way for a token around walls and hostile tokens
+move bob E5     -> move a token straight, or the cheapest way with --via-path
+next            -> advance initiative to the next token
+dash bob        -> a token dashes, its speed counts again this turn (the current one if omitted)
+disengage bob   -> a token moves without provoking opportunity attacks this turn
+strict on       -> refuse moves beyond a token's speed instead of warning (off)
+distance A1 C4  -> distance between two cells or tokens
+cover orc bob   -> how much cover a token has against an attacker, and its AC bonus
+attack orc bob  -> the AC to hit with cover, e.g. --ac=15, refused behind total cover
+calibrate       -> fit the grid by dragging a box over one square of the map (again to cancel)
+include x.game  -> run all commands from a game file
 q | quit | exit -> terminate programm
 r 3d6 + 5       -> roll dice and do some math
 r 2d20 K1       -> advantage (keep highest one)
 r 2d20 k1       -> disadvantage (keep lowest one)
-h | help | ?    -> print this help";
+h | help | ?    -> print this help
+help token      -> print the usage of a single command";
+
+/// Command names as listed in `HELP`, without duplicates.
+pub fn names() -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = vec![];
+    for line in HELP.lines().skip(1) {
+        let usage = line.split("->").next().unwrap_or("");
+        for alternative in usage.split('|') {
+            if let Some(name) = alternative.split_whitespace().next() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Usage of a single command: the generated clap help for commands with
+/// options, the matching `HELP` lines for everything else.
+pub fn usage(command: &str) -> Result<String, String> {
+    let mut help = vec![];
+    match command {
+        "token" => opts::Token::clap().write_long_help(&mut help),
+        "battlemap" => opts::Battlemap::clap().write_long_help(&mut help),
+        "move" => opts::Move::clap().write_long_help(&mut help),
+        "attack" => opts::Attack::clap().write_long_help(&mut help),
+        _ => {
+            let lines: Vec<&str> = HELP
+                .lines()
+                .skip(1)
+                .filter(|l| {
+                    let usage = l.split("->").next().unwrap_or("");
+                    usage
+                        .split('|')
+                        .any(|alt| alt.split_whitespace().next() == Some(command))
+                })
+                .collect();
+            return match lines.len() {
+                0 => Err(unknown_command(command)),
+                _ => Ok(lines.join("\n")),
+            };
+        }
+    }
+    .map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&help).into_owned())
+}
+
+fn unknown_command(command: &str) -> String {
+    let mut msg = format!("Unknown command: {}", command);
+    if let Some(name) = did_you_mean(command, names()) {
+        msg.push_str(&format!("\n\tDid you mean '{}'?", name));
+    }
+    msg.push_str("\n(type 'help' for a list of commands)");
+    msg
+}
+
+/// The candidate closest to `word`, if any is close enough to be a likely typo.
+pub fn did_you_mean<I, S>(word: &str, candidates: I) -> Option<S>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    candidates
+        .into_iter()
+        .map(|c| (strsim::jaro_winkler(word, c.as_ref()), c))
+        .filter(|(confidence, _)| *confidence > 0.8)
+        .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+        .map(|(_, c)| c)
+}
+
+#[test]
+fn test_did_you_mean() {
+    assert_eq!(Some("token"), did_you_mean("tokn", names()));
+    assert_eq!(Some("battlemap"), did_you_mean("batlemap", names()));
+    assert_eq!(None, did_you_mean("fireball", names()));
+    assert_eq!(
+        Some(&"goblinking".to_string()),
+        did_you_mean("goblinkng", &vec!["barb".to_string(), "goblinking".to_string()])
+    );
+}
+
+#[test]
+fn test_usage() {
+    assert!(usage("token").unwrap().contains("--max-health"));
+    assert!(usage("battlemap").unwrap().contains("--columns"));
+    assert_eq!(Ok("q | quit | exit -> terminate programm".to_string()), usage("quit"));
+    assert!(usage("tokn").unwrap_err().contains("Did you mean 'token'?"));
+}
+
+#[test]
+fn test_parse_keeps_clap_errors() {
+    match &parse("token bob --nmae=Bob".into())[0] {
+        Err(e) => {
+            assert!(e.contains("--nmae"), "{}", e);
+            assert!(e.contains("--name"), "{}", e);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match &parse("tokn bob".into())[0] {
+        Err(e) => assert!(e.contains("Did you mean 'token'?"), "{}", e),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_names() {
+    assert_eq!(
+        vec![
+            "battlemap", "token", "reveal", "shadow", "region", "terrain", "wall", "path", "move",
+            "next", "dash", "disengage",
+            "strict", "distance", "cover", "attack", "calibrate",
+            "include", "q", "quit", "exit", "r",
+            "h", "help", "?"
+        ],
+        names()
+    );
+}
+
+/// Split a line into words like a shell would: whitespace separates words,
+/// `'...'` quotes literally, `"..."` quotes with `\\` escapes and a backslash
+/// outside of quotes escapes the next character. An unquoted `#` at the start
+/// of a word comments out the rest of the line.
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '#' if !in_word => break,
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(format!("Unterminated ' quote in: {}", line)),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(format!("Unterminated \" quote in: {}", line)),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(format!("Unterminated \" quote in: {}", line)),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => return Err(format!("Dangling \\ at the end of: {}", line)),
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[test]
+fn test_split_args() {
+    assert_eq!(
+        Ok(vec!["token".to_string(), "bob".into(), "--name=Bob the Brave".into()]),
+        split_args("token bob --name=\"Bob the Brave\"")
+    );
+    assert_eq!(
+        Ok(vec!["a b".to_string(), "c\\d".into(), "\"".into(), "".into()]),
+        split_args("'a b'  c\\\\d \\\" ''")
+    );
+    assert_eq!(
+        Ok(vec!["--image=./my maps/it's here.png".to_string()]),
+        split_args("--image=./my\\ maps/\"it's here\".png")
+    );
+    assert!(split_args("token bob --name=\"Bob").is_err());
+    assert!(split_args("token bob --name='Bob").is_err());
+    assert!(split_args("token bob\\").is_err());
+}
+
+#[test]
+fn test_split_args_comments() {
+    assert_eq!(Ok(vec![]), split_args("# just a comment"));
+    assert_eq!(Ok(vec![]), split_args("   "));
+    assert_eq!(
+        Ok(vec!["h".to_string(), "barb".into(), "5".into()]),
+        split_args("h barb 5 # heal")
+    );
+    assert_eq!(
+        Ok(vec!["token".to_string(), "bob".into(), "--name=#1".into(), "#2".into()]),
+        split_args("token bob --name=#1 '#2'")
+    );
+}
+
+#[test]
+fn test_parse_skips_blank_lines_and_comments() {
+    assert!(parse("".into()).is_empty());
+    assert!(parse("  \n# comment\n".into()).is_empty());
+    assert_eq!(1, parse("\nq # bye\n".into()).len());
+}
+
+#[test]
+fn test_parse_quoted_token() {
+    let cmds = parse("token bob --name=\"Bob the Brave\" --image='./assets/my bob.png'".into());
+    match &cmds[0] {
+        Ok(Command::UpdateToken(t)) => {
+            assert_eq!(Some("Bob the Brave".to_string()), t.name);
+            assert_eq!(Some("./assets/my bob.png".to_string()), t.image);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse("token bob --name=\"Bob".into())[0].is_err());
+}
+
+fn area(words: &[String]) -> Result<Area, String> {
+    let cells = |s: &str| s.parse::<Cells>().map_err(|e| e.to_string());
+    match words {
+        [_] => Ok(Area::All),
+        [_, area] => cells(area).map(Area::Cells),
+        // the old way to write a rectangle, `reveal A2 F19`
+        [_, from, to] => cells(&format!("{}:{}", from, to)).map(Area::Cells),
+        _ => Err(format!("usage: {} [<cells>]", words[0])),
+    }
+}
+
+fn region(words: &[String]) -> Result<Command, String> {
+    match words {
+        [_, name, cells] if chess::is_region_name(name) => cells
+            .parse()
+            .map(|cells| Command::Region(name.to_owned(), cells))
+            .map_err(|e: chess::ParseError| e.to_string()),
+        [_, name, _] => Err(format!(
+            "{} can't name a region, names start with a letter and don't look like a coordinate",
+            name
+        )),
+        _ => Err("usage: region <name> <cells>".into()),
+    }
+}
+
+#[test]
+fn test_parse_area() {
+    let parsed = |l: &str| match parse(l.into()).remove(0) {
+        Ok(Command::Reveal(area)) => Ok(area),
+        other => Err(format!("{:?}", other)),
+    };
+    let rect = Area::Cells(Cells::Range((0, 1), (5, 18)));
+    assert_eq!(Ok(rect.clone()), parsed("reveal A2:F19"));
+    assert_eq!(Ok(rect), parsed("reveal A2 F19"));
+    assert_eq!(Ok(Area::Cells(Cells::Region("hall".into()))), parsed("reveal hall"));
+    assert!(parsed("reveal A2:").is_err());
+
+    match parse("region hall A1:B2".into()).remove(0) {
+        Ok(Command::Region(name, cells)) => {
+            assert_eq!("hall", name);
+            assert_eq!(Cells::Range((0, 0), (1, 1)), cells);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse("region C4 A1:B2".into())[0].is_err());
+    assert!(parse("region hall".into())[0].is_err());
+}
 
 pub fn parse(content: String) -> Vec<Result<Command, String>> {
     use Command::*;
     content
         .lines()
-        .map(|l| {
-            let words: Vec<_> = l.split_whitespace().collect();
-            match words[0] {
+        .filter_map(|l| -> Option<Result<Command, String>> {
+            let words = match split_args(l) {
+                Ok(w) => w,
+                Err(e) => return Some(Err(e)),
+            };
+            // blank lines and comments don't produce commands
+            let first = words.first()?;
+            Some(match first.as_str() {
                 "q" => Ok(Quit),
                 "quit" => Ok(Quit),
                 "exit" => Ok(Quit),
                 "r" => {
-                    let roller = caith::Roller::new(&l.to_owned().replace("r ", ""));
+                    let roller = caith::Roller::new(&words[1..].join(" "));
                     match roller {
                         Ok(r) => Ok(Role(r)),
                         Err(_) => Err(format!("Can't parse role from {}", l)),
                     }
                 }
-                "battlemap" => match opts::Battlemap::from_iter_safe(l.split_whitespace()) {
+                "reveal" => area(&words).map(Reveal),
+                "shadow" => area(&words).map(Shadow),
+                "region" => region(&words),
+                "terrain" => match words.get(1).map(String::as_str) {
+                    Some("difficult") => area(&words[1..]).map(|a| SetTerrain(Terrain::Difficult, a)),
+                    Some("normal") => area(&words[1..]).map(|a| SetTerrain(Terrain::Normal, a)),
+                    _ => Err(format!("usage: terrain difficult|normal [<cells>], got: {}", l)),
+                },
+                "wall" => match words.get(1).map(String::as_str) {
+                    Some(action @ "add") | Some(action @ "remove") if words.len() == 4 => {
+                        match (words[2].parse(), words[3].parse()) {
+                            (Ok(a), Ok(b)) => Ok(Wall(action == "add", a, b)),
+                            (Err(e), _) | (_, Err(e)) => Err(e.to_string()),
+                        }
+                    }
+                    _ => Err(format!("usage: wall add|remove <cells> <cells>, got: {}", l)),
+                },
+                "path" => match words.len() {
+                    3 => Ok(FindPath(words[1].to_owned(), words[2].to_owned())),
+                    _ => Err(format!("usage: path <token> <cell>, got: {}", l)),
+                },
+                "move" => match opts::Move::from_iter_safe(&words) {
+                    Ok(m) if m.via_path => Ok(MoveAlongPath(m)),
+                    // going straight is just a new position
+                    Ok(m) => {
+                        let mut args = vec!["token".to_string(), m.token_id, format!("--pos={}", m.cell)];
+                        let flags = [(m.fly, "--fly"), (m.swim, "--swim"), (m.climb, "--climb")];
+                        args.extend(flags.iter().filter(|f| f.0).map(|f| f.1.to_string()));
+                        opts::Token::from_iter_safe(&args).map(UpdateToken).map_err(|e| e.message)
+                    }
+                    Err(e) => Err(e.message),
+                },
+                "dash" => match words.len() {
+                    1 => Ok(Dash(None)),
+                    2 => Ok(Dash(Some(words[1].to_owned()))),
+                    _ => Err(format!("usage: dash [<token>], got: {}", l)),
+                },
+                "disengage" => match words.len() {
+                    1 => Ok(Disengage(None)),
+                    2 => Ok(Disengage(Some(words[1].to_owned()))),
+                    _ => Err(format!("usage: disengage [<token>], got: {}", l)),
+                },
+                "strict" => match words.get(1).map(String::as_str) {
+                    Some("on") if words.len() == 2 => Ok(Strict(true)),
+                    Some("off") if words.len() == 2 => Ok(Strict(false)),
+                    _ => Err(format!("usage: strict on|off, got: {}", l)),
+                },
+                "next" => Ok(NextTurn),
+                "distance" => match words.len() {
+                    3 => Ok(Distance(words[1].to_owned(), words[2].to_owned())),
+                    _ => Err(format!("usage: distance <from> <to>, got: {}", l)),
+                },
+                "cover" => match words.len() {
+                    3 => Ok(CheckCover(words[1].to_owned(), words[2].to_owned())),
+                    _ => Err(format!("usage: cover <attacker> <target>, got: {}", l)),
+                },
+                "attack" => opts::Attack::from_iter_safe(&words).map(Attack).map_err(|e| e.message),
+                "calibrate" => Ok(Calibrate),
+                "include" => match words.len() {
+                    2 => Ok(Include(words[1].to_owned())),
+                    _ => Err(format!("usage: include <file>, got: {}", l)),
+                },
+                "h" | "help" | "?" => match words.len() {
+                    1 => Ok(PrintHelp(None)),
+                    _ => Ok(PrintHelp(Some(words[1].to_owned()))),
+                },
+                "battlemap" => match opts::Battlemap::from_iter_safe(&words) {
                     Ok(x) => Ok(UpdateBattlemap(x)),
-                    Err(_) => Err(format!("Can't parse battlemap command from {}", l)),
+                    Err(e) => Err(e.message),
                 },
                 "token" => {
-                    match opts::Token::from_iter_safe(l.split_whitespace()) {
+                    match opts::Token::from_iter_safe(&words) {
                         Ok(x) => Ok(UpdateToken(x)),
-                        Err(_) => Err(format!("Can't parse token command from {}", l)),
+                        Err(e) => Err(e.message),
                     }
                 },
-                _ => Ok(PrintHelp(l.to_owned())),
-            }
+                unknown => Err(unknown_command(unknown)),
+            })
         })
         .collect()
 }
@@ -0,0 +1,142 @@
+use tetra::graphics::text::{Font, Text};
+use tetra::graphics::{self, Color, DrawParams, Texture};
+use tetra::input::{self, Key};
+use tetra::math::Vec2;
+use tetra::{window, Context};
+
+const TOGGLE_KEY: Key = Key::Backquote;
+const VISIBLE_LINES: usize = 12;
+const MAX_SCROLLBACK: usize = 500;
+const LINE_HEIGHT: f32 = 24.0;
+const PADDING: f32 = 8.0;
+const BACKGROUND_COLOR: Color = Color::rgba(0.0, 0.0, 0.0, 0.75);
+const TEXT_COLOR: Color = Color::rgb(0.85, 0.85, 0.85);
+
+/// Quake style drop down terminal. Lines submitted here are fed into the same
+/// pipeline as lines typed on stdin.
+pub struct Console {
+    pub visible: bool,
+    input: String,
+    scrollback: Vec<String>,
+    history: Vec<String>,
+    history_pos: Option<usize>,
+    text: Text,
+    background: Texture,
+}
+
+impl Console {
+    pub fn new(ctx: &mut Context, font: Font) -> tetra::Result<Console> {
+        Ok(Console {
+            visible: false,
+            input: String::new(),
+            scrollback: Vec::new(),
+            history: Vec::new(),
+            history_pos: None,
+            text: Text::new("", font),
+            background: Texture::from_rgba(ctx, 1, 1, &[255, 255, 255, 255])?,
+        })
+    }
+
+    pub fn log<S: Into<String>>(&mut self, line: S) {
+        for l in line.into().lines() {
+            self.scrollback.push(l.to_owned());
+        }
+        if self.scrollback.len() > MAX_SCROLLBACK {
+            let overflow = self.scrollback.len() - MAX_SCROLLBACK;
+            self.scrollback.drain(0..overflow);
+        }
+    }
+
+    /// Handle this frame's keyboard input and return a line if one was submitted.
+    pub fn update(&mut self, ctx: &mut Context) -> Option<String> {
+        if input::is_key_pressed(ctx, TOGGLE_KEY) {
+            self.visible = !self.visible;
+            return None;
+        }
+        if !self.visible {
+            return None;
+        }
+
+        if let Some(typed) = input::get_text_input(ctx) {
+            // the toggle key also produces a character; don't type it
+            self.input.extend(typed.chars().filter(|&c| c != '`'));
+        }
+
+        if input::is_key_pressed(ctx, Key::Backspace) {
+            self.input.pop();
+        }
+
+        if input::is_key_pressed(ctx, Key::Up) {
+            self.recall(|pos, len| match pos {
+                None if len > 0 => Some(len - 1),
+                Some(p) if p > 0 => Some(p - 1),
+                p => p,
+            });
+        }
+
+        if input::is_key_pressed(ctx, Key::Down) {
+            self.recall(|pos, len| match pos {
+                Some(p) if p + 1 < len => Some(p + 1),
+                _ => None,
+            });
+        }
+
+        if input::is_key_pressed(ctx, Key::Enter) {
+            let line = self.input.trim().to_owned();
+            self.input.clear();
+            self.history_pos = None;
+            if line.is_empty() {
+                return None;
+            }
+            if self.history.last() != Some(&line) {
+                self.history.push(line.to_owned());
+            }
+            return Some(line);
+        }
+
+        None
+    }
+
+    fn recall<F: Fn(Option<usize>, usize) -> Option<usize>>(&mut self, step: F) {
+        self.history_pos = step(self.history_pos, self.history.len());
+        self.input = match self.history_pos {
+            Some(p) => self.history[p].to_owned(),
+            None => String::new(),
+        };
+    }
+
+    pub fn render(&mut self, ctx: &mut Context) {
+        if !self.visible {
+            return;
+        }
+
+        let width = window::get_width(ctx) as f32;
+        let height = (VISIBLE_LINES + 1) as f32 * LINE_HEIGHT + 2.0 * PADDING;
+        graphics::draw(
+            ctx,
+            &self.background,
+            DrawParams::default()
+                .scale(Vec2::new(width, height))
+                .color(BACKGROUND_COLOR),
+        );
+
+        let start = self.scrollback.len().saturating_sub(VISIBLE_LINES);
+        let mut content = self.scrollback[start..].join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        content.push_str(&format!("> {}_", self.input));
+        self.text.set_content(content);
+
+        // keep the prompt pinned to the bottom of the console
+        let shown = self.scrollback.len().min(VISIBLE_LINES) + 1;
+        let y = height - PADDING - shown as f32 * LINE_HEIGHT;
+        graphics::draw(
+            ctx,
+            &self.text,
+            DrawParams::default()
+                .position(Vec2::new(PADDING, y))
+                .color(TEXT_COLOR),
+        );
+    }
+}
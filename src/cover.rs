@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use std::fmt;
+
+/// How much of a target is shielded from an attacker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cover {
+    None,
+    Half,
+    ThreeQuarters,
+    Total,
+}
+
+impl Cover {
+    /// What the cover adds to the target's AC, `None` if it can't be targeted.
+    pub fn ac_bonus(self) -> Option<i32> {
+        match self {
+            Cover::None => Some(0),
+            Cover::Half => Some(2),
+            Cover::ThreeQuarters => Some(5),
+            Cover::Total => None,
+        }
+    }
+}
+
+impl fmt::Display for Cover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self, self.ac_bonus()) {
+            (Cover::None, _) => write!(f, "no cover"),
+            (Cover::Half, Some(ac)) => write!(f, "half cover (+{} AC)", ac),
+            (Cover::ThreeQuarters, Some(ac)) => write!(f, "three-quarters cover (+{} AC)", ac),
+            _ => write!(f, "total cover (can't be targeted)"),
+        }
+    }
+}
+
+type Point = (i32, i32);
+
+/// Two neighbouring cells with a wall between them.
+pub type Wall = ((i32, i32), (i32, i32));
+
+/// The cover of the `target` cell against the `attacker` cell on a square
+/// grid, after the DMG: the attacker picks a corner of its cell and draws
+/// lines to the four corners of the target's cell. One or two blocked lines
+/// give half cover, three or four three-quarters. Creatures never hide a
+/// target completely, total cover needs walls blocking all four lines.
+///
+/// Lines are blocked by passing through one of the `obstacles` cells or by
+/// touching a wall between two of the `walls` cells or between two
+/// neighbouring obstacles. Lines ending on a wall are only blocked by the
+/// walls around the attacker's or the target's cell they come through, so a
+/// wall behind the target doesn't shield it.
+pub fn cover(
+    attacker: (i32, i32),
+    target: (i32, i32),
+    obstacles: &[(i32, i32)],
+    walls: &HashSet<Wall>,
+) -> Cover {
+    // creatures side by side leave no gap between them
+    let between = obstacles.iter().flat_map(|&a| {
+        obstacles
+            .iter()
+            .filter(move |&&b| a < b && (a.0 - b.0).abs() <= 1 && (a.1 - b.1).abs() <= 1)
+            .map(move |&b| (a, b))
+    });
+    let sides: Vec<_> = walls.iter().filter_map(|&(a, b)| side(attacker, a, b)).collect();
+    let guards: Vec<_> = walls.iter().filter_map(|&(a, b)| side(target, a, b)).collect();
+    let gaps: Vec<(Point, Point)> = between.map(|(a, b)| edge(a, b)).collect();
+    let walls: Vec<(Point, Point)> = walls.iter().map(|&(a, b)| edge(a, b)).collect();
+    let walled = |from: Point, to: Point| {
+        walls.iter().any(|&(a, b)| touches(from, to, a, b))
+            || sides.iter().any(|side| side.shuts(from, to))
+            || guards.iter().any(|side| side.shuts(to, from))
+    };
+    let blocked = |from: Point, to: Point| {
+        walled(from, to)
+            || obstacles.iter().any(|&cell| passes_through(from, to, cell))
+            || gaps.iter().any(|&(a, b)| touches(from, to, a, b))
+    };
+    let count = |from: Point, blocks: &dyn Fn(Point, Point) -> bool| {
+        corners(target).iter().filter(|&&to| blocks(from, to)).count()
+    };
+    if corners(attacker).iter().all(|&from| count(from, &walled) == 4) {
+        return Cover::Total;
+    }
+    match corners(attacker).iter().map(|&from| count(from, &blocked)).min().unwrap_or(0) {
+        0 => Cover::None,
+        1 | 2 => Cover::Half,
+        _ => Cover::ThreeQuarters,
+    }
+}
+
+fn corners((col, row): (i32, i32)) -> [Point; 4] {
+    [(col, row), (col + 1, row), (col, row + 1), (col + 1, row + 1)]
+}
+
+/// The side two neighbouring cells share, or the corner for diagonal ones.
+fn edge(a: (i32, i32), b: (i32, i32)) -> (Point, Point) {
+    let corner = (a.0.max(b.0), a.1.max(b.1));
+    match (a.0 == b.0, a.1 == b.1) {
+        (true, _) => ((a.0, corner.1), (a.0 + 1, corner.1)),
+        (_, true) => ((corner.0, a.1), (corner.0, a.1 + 1)),
+        _ => (corner, corner),
+    }
+}
+
+/// A wall on one side of a cell: the grid line it lies on, as an axis and a
+/// position on it, and the direction of the cell beyond it.
+struct Side {
+    axis: usize,
+    at: i32,
+    beyond: i32,
+    corners: (Point, Point),
+}
+
+impl Side {
+    /// Whether a line from `corner`, one of the ends of the wall, to `other`
+    /// goes through it.
+    fn shuts(&self, corner: Point, other: Point) -> bool {
+        let coordinate = |p: Point| if self.axis == 0 { p.0 } else { p.1 };
+        (corner == self.corners.0 || corner == self.corners.1)
+            && (coordinate(other) - self.at).signum() == self.beyond
+    }
+}
+
+/// The wall between neighbouring cells `a` and `b` if it's a side of `cell`.
+fn side(cell: (i32, i32), a: (i32, i32), b: (i32, i32)) -> Option<Side> {
+    let other = match cell {
+        _ if cell == a => b,
+        _ if cell == b => a,
+        _ => return None,
+    };
+    let (axis, beyond) = match (other.0 - cell.0, other.1 - cell.1) {
+        (d, 0) => (0, d),
+        (0, d) => (1, d),
+        _ => return None,
+    };
+    let corners = edge(cell, other);
+    let at = if axis == 0 { corners.0 .0 } else { corners.0 .1 };
+    Some(Side { axis, at, beyond, corners })
+}
+
+/// Whether the line from `p` to `q` runs through the inside of `cell`, not
+/// just along its sides or over its corners.
+fn passes_through(p: Point, q: Point, (col, row): (i32, i32)) -> bool {
+    let (mut enter, mut leave) = (0.0, 1.0);
+    for &(start, delta, low) in &[(p.0, q.0 - p.0, col), (p.1, q.1 - p.1, row)] {
+        // corners lie on the grid lines, so a line along one never gets inside
+        if delta == 0 {
+            return false;
+        }
+        let (a, b) = (
+            (low - start) as f32 / delta as f32,
+            (low + 1 - start) as f32 / delta as f32,
+        );
+        enter = a.min(b).max(enter);
+        leave = a.max(b).min(leave);
+    }
+    enter < leave
+}
+
+/// Whether the line from `p` to `q` meets the wall from `a` to `b` anywhere
+/// but at its own ends.
+fn touches(p: Point, q: Point, a: Point, b: Point) -> bool {
+    let side = |from: Point, to: Point, x: Point| {
+        ((to.0 - from.0) * (x.1 - from.1) - (to.1 - from.1) * (x.0 - from.0)).signum()
+    };
+    // how far along the line a point lies, 0 at `p` and `len` at `q`
+    let len = (q.0 - p.0).pow(2) + (q.1 - p.1).pow(2);
+    let along = |x: Point| (x.0 - p.0) * (q.0 - p.0) + (x.1 - p.1) * (q.1 - p.1);
+    let inside = |x: Point| side(p, q, x) == 0 && 0 < along(x) && along(x) < len;
+
+    let (sp, sq) = (side(a, b, p), side(a, b, q));
+    let (sa, sb) = (side(p, q, a), side(p, q, b));
+    if sp * sq < 0 && sa * sb < 0 {
+        return true;
+    }
+    if a != b && sp == 0 && sq == 0 {
+        // along the wall
+        let (lo, hi) = (along(a).min(along(b)), along(a).max(along(b)));
+        return lo.max(0) < hi.min(len);
+    }
+    inside(a) || inside(b)
+}
+
+#[test]
+fn test_cover() {
+    let walls = HashSet::new();
+    assert_eq!(Cover::None, cover((0, 0), (4, 0), &[], &walls));
+    // a creature in between shields half
+    assert_eq!(Cover::Half, cover((0, 0), (4, 0), &[(2, 0)], &walls));
+    // more of it when right on the line between them
+    assert_eq!(Cover::ThreeQuarters, cover((0, 0), (4, 2), &[(2, 1)], &walls));
+    // the attacker picks the corner that sees most
+    assert_eq!(Cover::None, cover((0, 0), (4, 4), &[(2, 0), (0, 2)], &walls));
+    // creatures never give total cover
+    assert_eq!(Cover::ThreeQuarters, cover((0, 0), (4, 0), &[(2, -1), (2, 0), (2, 1)], &walls));
+
+    let wall = |cells: &[Wall]| cells.iter().cloned().collect::<HashSet<_>>();
+    // behind a wall along the whole side
+    let side = wall(&[((2, -1), (3, -1)), ((2, 0), (3, 0)), ((2, 1), (3, 1))]);
+    assert_eq!(Cover::Total, cover((0, 0), (4, 0), &[], &side));
+    // a gap as wide as the target
+    let gap = wall(&[((2, -1), (3, -1)), ((2, 1), (3, 1))]);
+    assert_eq!(Cover::Half, cover((0, 0), (4, 0), &[], &gap));
+    // not even together with walls
+    assert_eq!(Cover::ThreeQuarters, cover((0, 0), (4, 0), &[(2, 0)], &gap));
+    // standing against a wall, in front of it or behind it
+    assert_eq!(Cover::Total, cover((0, 0), (4, 0), &[], &wall(&[((3, 0), (4, 0))])));
+    assert_eq!(Cover::None, cover((0, 0), (2, 0), &[], &wall(&[((2, 0), (3, 0))])));
+}
+
+#[test]
+fn test_touches() {
+    // crossing, touching the middle, ending on it, and running along it
+    assert!(touches((0, 0), (2, 2), (0, 2), (2, 0)));
+    assert!(touches((0, 0), (2, 0), (1, 0), (1, 1)));
+    assert!(!touches((0, 0), (2, 0), (2, 0), (2, 1)));
+    assert!(touches((0, 0), (3, 0), (1, 0), (2, 0)));
+    assert!(!touches((0, 0), (1, 0), (1, 0), (2, 0)));
+    // a diagonal wall is a single corner
+    assert!(touches((0, 0), (2, 2), (1, 1), (1, 1)));
+    assert!(!touches((0, 0), (2, 1), (1, 1), (1, 1)));
+}
+
@@ -0,0 +1,144 @@
+use crate::domain::{Grid, Shape};
+
+use image::GrayImage;
+
+/// Detections below this are more likely texture than a printed grid.
+pub const MIN_CONFIDENCE: f32 = 0.5;
+/// Smaller cells can't be told apart from texture.
+const MIN_CELL: usize = 8;
+/// A period is only trusted if it repeats at least this often.
+const MIN_REPEATS: usize = 3;
+/// Multiples of the cell size score about as well as the cell size itself,
+/// so the smallest period scoring this close to the best one wins.
+const HARMONIC_TOLERANCE: f32 = 0.9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detection {
+    pub grid: Grid,
+    pub confidence: f32, // 0 for noise, close to 1 for a crisp grid
+}
+
+/// Estimate the printed grid of a battlemap image.
+///
+/// Grid lines are edges that repeat at a fixed pitch, so the horizontal and
+/// vertical gradients are summed up per column and per row. Each of these
+/// profiles is then folded at every candidate pitch; the pitch where the edges
+/// pile up in the fewest phases is the cell size, the phase gives the offset.
+/// Returns `None` if the image is too small to hold a few cells.
+pub fn grid(img: &GrayImage) -> Option<Detection> {
+    let (width, height) = img.dimensions();
+    let px = |x: u32, y: u32| img.get_pixel(x, y)[0] as f32;
+
+    let mut columns = vec![0.0; width.saturating_sub(1) as usize];
+    let mut rows = vec![0.0; height.saturating_sub(1) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            if x + 1 < width {
+                columns[x as usize] += (px(x + 1, y) - px(x, y)).abs();
+            }
+            if y + 1 < height {
+                rows[y as usize] += (px(x, y + 1) - px(x, y)).abs();
+            }
+        }
+    }
+
+    let (cell_w, line_x, confidence_x) = period(&columns)?;
+    let (cell_h, line_y, confidence_y) = period(&rows)?;
+    // a gradient at `i` lies between pixel `i` and `i + 1`
+    let (x, y) = (line_x + 0.5, line_y + 0.5);
+    Some(Detection {
+        grid: Grid::calibrate(Shape::Square, (x, y), (x + cell_w as f32, y + cell_h as f32))?,
+        confidence: confidence_x.min(confidence_y),
+    })
+}
+
+/// The pitch at which the peaks of `profile` repeat, where within a pitch the
+/// peaks lie and how clearly they stand out from the rest.
+fn period(profile: &[f32]) -> Option<(usize, f32, f32)> {
+    let max_period = profile.len() / MIN_REPEATS;
+    if max_period < MIN_CELL {
+        return None;
+    }
+    let mean = profile.iter().sum::<f32>() / profile.len() as f32;
+    if mean == 0.0 {
+        return None;
+    }
+
+    let candidates: Vec<(usize, usize, f32)> = (MIN_CELL..=max_period)
+        .map(|p| {
+            let bins = fold(profile, p);
+            // a window of three bins tolerates lines that are thick or not
+            // exactly on an integer pitch
+            let window = |i: usize| bins[(i + p - 1) % p] + bins[i] + bins[(i + 1) % p];
+            let phase = (0..p)
+                .max_by(|a, b| window(*a).partial_cmp(&window(*b)).unwrap())
+                .unwrap();
+            (p, phase, window(phase))
+        })
+        .collect();
+    let best = candidates.iter().map(|c| c.2).fold(0.0, f32::max);
+    let &(p, phase, score) = candidates
+        .iter()
+        .find(|c| c.2 >= best * HARMONIC_TOLERANCE)?;
+
+    // centre of the edges around the peak, so both sides of a thick line count
+    let bins = fold(profile, p);
+    let (mut weight, mut centre) = (0.0, 0.0);
+    for d in -(p as i32 / 4)..=p as i32 / 4 {
+        let b = bins[(phase as i32 + d).rem_euclid(p as i32) as usize];
+        weight += b;
+        centre += b * d as f32;
+    }
+    let line = phase as f32 + centre / weight;
+    let confidence = ((score - 3.0 * mean) / score).max(0.0);
+    Some((p, line, confidence))
+}
+
+/// Average of the profile at every phase of the period `p`.
+fn fold(profile: &[f32], p: usize) -> Vec<f32> {
+    let mut sums = vec![0.0; p];
+    let mut counts = vec![0; p];
+    for (i, v) in profile.iter().enumerate() {
+        sums[i % p] += v;
+        counts[i % p] += 1;
+    }
+    sums.iter().zip(counts).map(|(s, c)| s / c as f32).collect()
+}
+
+/// A light image with dark grid lines, `line` pixels wide.
+#[cfg(test)]
+pub fn gridded(width: u32, height: u32, grid: Grid, line: u32) -> GrayImage {
+    let on_line = |v: u32, offset: i32, cell: u32| {
+        ((v as i32 - offset).rem_euclid(cell as i32) as u32) < line
+    };
+    GrayImage::from_fn(width, height, |x, y| {
+        if on_line(x, grid.offset.0, grid.cell.0) || on_line(y, grid.offset.1, grid.cell.1) {
+            image::Luma([40])
+        } else {
+            image::Luma([200])
+        }
+    })
+}
+
+#[test]
+fn test_detect_grid() {
+    let expected = Grid { offset: (13, -20), cell: (40, 50), shape: Shape::Square };
+    let detected = grid(&gridded(400, 300, expected, 1)).unwrap();
+    assert_eq!(expected, detected.grid);
+    assert!(detected.confidence > 0.9, "{:?}", detected);
+
+    // thick lines: the cell edge is the middle of the line
+    let thick = Grid { offset: (10, 10), cell: (70, 70), shape: Shape::Square };
+    let detected = grid(&gridded(700, 500, thick, 4)).unwrap();
+    assert_eq!(Grid { offset: (12, 12), cell: (70, 70), shape: Shape::Square }, detected.grid);
+}
+
+#[test]
+fn test_detect_no_grid() {
+    use rand::{Rng, SeedableRng};
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let noise = GrayImage::from_fn(300, 300, |_, _| image::Luma([rng.gen()]));
+    assert!(grid(&noise).unwrap().confidence < MIN_CONFIDENCE);
+    assert_eq!(None, grid(&GrayImage::from_pixel(300, 300, image::Luma([90]))));
+    assert_eq!(None, grid(&GrayImage::new(20, 20)));
+}
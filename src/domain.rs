@@ -1,131 +1,1719 @@
-use crate::chess;
-use tetra::graphics::text::Font;
-use tetra::graphics::text::Text;
-use tetra::graphics::{self, Color, DrawParams, Texture};
-use tetra::math::Vec2;
-use tetra::Context;
+use crate::chess::{self, Cells, Coordinates};
+use crate::commands::{self, Area, Command, Terrain};
+use crate::cover::{self, Cover};
+use crate::hex::{self, Orientation};
+use crate::path;
 
-const EDGE_WIDTH: i32 = 3;
-const GRID_COORD_COL: Color = Color::rgba(0.0, 0.0, 0.0, 0.25);
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 
+/// Feet covered by a step from one cell to the next.
+pub const FEET_PER_CELL: i32 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Battlemap {
-    tile_canvas: graphics::Canvas,
-    pub image_path: String,
-    pub texture: Texture,
+    pub image_path: Option<String>, // a placeholder is drawn without one
     pub rows: i32,
     pub columns: i32,
+    pub cell_size: Option<(u32, u32)>, // fitted to the image without one
+    pub offset: (i32, i32),            // top left corner of A1 on the image
+    pub shape: Shape,
 }
 
-impl Battlemap {
-    pub fn new(ctx: &mut Context, image_path: String, rows: i32, columns: i32) -> Self {
-        let texture =
-            Texture::new(ctx, image_path.to_owned()).expect(format!("Can't read file.").as_str());
-        let tile_canvas = Self::new_tile_canvas(rows, columns, &texture, ctx)
-            .expect("Failed to create tile canvas.");
+impl Default for Battlemap {
+    fn default() -> Self {
         Battlemap {
-            image_path: image_path.to_owned(),
-            tile_canvas,
-            texture,
-            rows,
-            columns,
+            image_path: Some("./assets/bg_placeholder.jpg".into()),
+            rows: 12,
+            columns: 20,
+            cell_size: None,
+            offset: (0, 0),
+            shape: Shape::Square,
+        }
+    }
+}
+
+/// The shape of the cells on a battlemap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Square,
+    Hex(Orientation),
+    Gridless, // invisible squares, tokens can also stand anywhere in between
+}
+
+impl std::str::FromStr for Shape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Shape::Square),
+            "hex-pointy" => Ok(Shape::Hex(Orientation::Pointy)),
+            "hex-flat" => Ok(Shape::Hex(Orientation::Flat)),
+            "none" => Ok(Shape::Gridless),
+            _ => Err(format!(
+                "unknown grid {}, expected square, hex-pointy, hex-flat or none",
+                s
+            )),
+        }
+    }
+}
+
+impl Shape {
+    /// Number of steps between two cells. Diagonal steps on a square grid
+    /// count as one, as in the Player's Handbook.
+    pub fn distance(&self, a: (i32, i32), b: (i32, i32)) -> i32 {
+        match self {
+            Shape::Square | Shape::Gridless => (a.0 - b.0).abs().max((a.1 - b.1).abs()),
+            Shape::Hex(orientation) => hex::distance(*orientation, a, b),
+        }
+    }
+
+    /// The cells a straight move from `a` to `b` passes through, one per step
+    /// and without `a`.
+    pub fn line(&self, a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+        match self {
+            Shape::Square | Shape::Gridless => {
+                let n = self.distance(a, b);
+                let lerp = |p: i32, q: i32, t: f32| p + ((q - p) as f32 * t).round() as i32;
+                (1..=n)
+                    .map(|i| {
+                        let t = i as f32 / n as f32;
+                        (lerp(a.0, b.0, t), lerp(a.1, b.1, t))
+                    })
+                    .collect()
+            }
+            Shape::Hex(orientation) => hex::line(*orientation, a, b),
+        }
+    }
+
+    /// The cells one step away from `cell`, which may be off the battlemap.
+    pub fn neighbours(&self, (col, row): (i32, i32)) -> Vec<(i32, i32)> {
+        match self {
+            Shape::Square | Shape::Gridless => (-1..=1)
+                .flat_map(|dc| (-1..=1).map(move |dr| (col + dc, row + dr)))
+                .filter(|&cell| cell != (col, row))
+                .collect(),
+            Shape::Hex(orientation) => hex::neighbours(*orientation, (col, row)),
+        }
+    }
+
+    /// Per axis, the distance between neighbouring cells and how far the last
+    /// cell sticks out beyond that, both in cell sizes. Hexes interlock, so
+    /// their rows (pointy) or columns (flat) are closer than a cell apart.
+    fn layout(&self) -> [(f32, f32); 2] {
+        match self {
+            Shape::Square | Shape::Gridless => [(1.0, 0.0), (1.0, 0.0)],
+            Shape::Hex(Orientation::Pointy) => [(1.0, 0.5), (0.75, 0.25)],
+            Shape::Hex(Orientation::Flat) => [(0.75, 0.25), (1.0, 0.5)],
         }
     }
 
-    pub fn render(&mut self, ctx: &mut Context) {
-        // Now all drawing operations will be transformed:
-        graphics::draw(ctx, &self.texture, DrawParams::new());
-        self.render_grid(ctx)
+    /// Corners of a cell, relative to the top left of its bounding box and in
+    /// cell sizes.
+    fn corners(&self) -> &'static [(f32, f32)] {
+        match self {
+            Shape::Square | Shape::Gridless => &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+            Shape::Hex(Orientation::Pointy) => &[
+                (0.5, 0.0),
+                (1.0, 0.25),
+                (1.0, 0.75),
+                (0.5, 1.0),
+                (0.0, 0.75),
+                (0.0, 0.25),
+            ],
+            Shape::Hex(Orientation::Flat) => &[
+                (0.25, 0.0),
+                (0.75, 0.0),
+                (1.0, 0.5),
+                (0.75, 1.0),
+                (0.25, 1.0),
+                (0.0, 0.5),
+            ],
+        }
+    }
+}
+
+/// Where the cells lie on the battlemap image, in pixels. `cell` is the size
+/// of a cell's bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub offset: (i32, i32),
+    pub cell: (u32, u32),
+    pub shape: Shape,
+}
+
+impl Grid {
+    /// Top left pixel of a cell's bounding box.
+    pub fn origin(&self, cell: (i32, i32)) -> (i32, i32) {
+        let (x, y) = self.corner(cell);
+        (x.round() as i32, y.round() as i32)
+    }
+
+    /// Middle of a cell, where its token stands.
+    pub fn center(&self, cell: (i32, i32)) -> (f32, f32) {
+        let (x, y) = self.corner(cell);
+        (x + self.cell.0 as f32 / 2.0, y + self.cell.1 as f32 / 2.0)
+    }
+
+    /// Outline of a cell, clockwise from the top.
+    pub fn corners(&self, cell: (i32, i32)) -> Vec<(f32, f32)> {
+        let (x, y) = self.corner(cell);
+        let (w, h) = (self.cell.0 as f32, self.cell.1 as f32);
+        self.shape
+            .corners()
+            .iter()
+            .map(|(cx, cy)| (x + cx * w, y + cy * h))
+            .collect()
+    }
+
+    /// The cell containing a pixel, which may well be off the battlemap.
+    pub fn cell_at(&self, (x, y): (f32, f32)) -> (i32, i32) {
+        let [(step_x, _), (step_y, _)] = self.shape.layout();
+        let (w, h) = (self.cell.0 as f32, self.cell.1 as f32);
+        let guess = (
+            ((x - self.offset.0 as f32) / (step_x * w)).floor() as i32,
+            ((y - self.offset.1 as f32) / (step_y * h)).floor() as i32,
+        );
+        let orientation = match self.shape {
+            Shape::Square | Shape::Gridless => return guess,
+            Shape::Hex(orientation) => orientation,
+        };
+        // Hexes overlap the bands the guess is based on, so it can be one cell
+        // off. The closest centre wins, measured as if the hexes were regular.
+        let (scale_x, scale_y) = match orientation {
+            Orientation::Pointy => (3f32.sqrt() / w, 2.0 / h),
+            Orientation::Flat => (2.0 / w, 3f32.sqrt() / h),
+        };
+        let distance = |cell: (i32, i32)| {
+            let (cx, cy) = self.center(cell);
+            ((x - cx) * scale_x).powi(2) + ((y - cy) * scale_y).powi(2)
+        };
+        (-1..=1)
+            .flat_map(|dc| (-1..=1).map(move |dr| (guess.0 + dc, guess.1 + dr)))
+            .min_by(|a, b| distance(*a).partial_cmp(&distance(*b)).unwrap())
+            .unwrap()
     }
 
-    pub fn grid_size(&self) -> (i32, i32) {
+    /// The grid of a given shape that has the box from `a` to `b` as one of
+    /// its cells. The offset is moved to the cell closest to the top left
+    /// corner of the image, so A1 is the first cell that is at least half
+    /// visible. Boxes smaller than 2x2 pixels are rejected as stray clicks.
+    pub fn calibrate(shape: Shape, a: (f32, f32), b: (f32, f32)) -> Option<Grid> {
+        let cell = ((a.0 - b.0).abs().round() as u32, (a.1 - b.1).abs().round() as u32);
+        if cell.0 < 2 || cell.1 < 2 {
+            return None;
+        }
+        // staggered hexes only repeat every other column or row
+        let [(step_x, _), (step_y, _)] = shape.layout();
+        let (stagger_x, stagger_y) = match shape {
+            Shape::Hex(Orientation::Flat) => (2.0, 1.0),
+            Shape::Hex(Orientation::Pointy) => (1.0, 2.0),
+            Shape::Square | Shape::Gridless => (1.0, 1.0),
+        };
+        let first = |corner: f32, period: f32| {
+            let period = period.round() as i32;
+            let offset = (corner.round() as i32).rem_euclid(period);
+            if offset * 2 > period {
+                offset - period
+            } else {
+                offset
+            }
+        };
+        Some(Grid {
+            offset: (
+                first(a.0.min(b.0), cell.0 as f32 * step_x * stagger_x),
+                first(a.1.min(b.1), cell.1 as f32 * step_y * stagger_y),
+            ),
+            cell,
+            shape,
+        })
+    }
+
+    /// Columns and rows that fit on a `width`x`height` image. A partial cell
+    /// at the right or bottom edge counts if at least half of it is visible.
+    pub fn count(&self, (width, height): (u32, u32)) -> (i32, i32) {
+        let [x, y] = self.shape.layout();
+        let fit = |size: u32, offset: i32, cell: u32, (step, overhang): (f32, f32)| {
+            let cells = (size as i32 - offset) as f32 / cell as f32;
+            ((cells - overhang) / step).round().max(1.0) as i32
+        };
         (
-            (self.texture.width() as f64 / self.columns as f64).round() as i32,
-            (self.texture.height() as f64 / self.rows as f64).round() as i32,
+            fit(width, self.offset.0, self.cell.0, x),
+            fit(height, self.offset.1, self.cell.1, y),
         )
     }
 
-    fn new_tile_canvas(
-        rows: i32,
-        columns: i32,
-        texture: &Texture,
-        ctx: &mut Context,
-    ) -> tetra::Result<graphics::Canvas> {
-        let tile_w = (texture.width() as f64 / columns as f64).round() as i32;
-        let tile_h = (texture.height() as f64 / rows as f64).round() as i32;
-
-        // right edge
-        let c = graphics::Canvas::new(ctx, tile_w, tile_h)?;
-        let data = vec![128 as u8; (4 * tile_h * EDGE_WIDTH) as usize];
-        let (x, y, width, height) = (tile_w - EDGE_WIDTH, 0, EDGE_WIDTH, tile_h);
-        c.set_data(ctx, x, y, width, height, &*data)?;
-
-        // bottom edge
-        let data = vec![128 as u8; (4 * tile_w * EDGE_WIDTH) as usize];
-        let (x, y, width, height) = (0, tile_h - EDGE_WIDTH, tile_w, EDGE_WIDTH);
-        c.set_data(ctx, x, y, width, height, &*data)?;
-        Ok(c)
-    }
-
-    fn render_grid(&mut self, ctx: &mut Context) {
-        let (tile_w, tile_h) = self.grid_size();
-        // TODO cache assests in global game state
-        let font = Font::vector(ctx, "./assets/SourceCodePro-Black.ttf", 32.0)
-            .expect("Failed to load font.");
-        for col in 0..self.columns {
-            for row in 0..self.rows {
-                let pos = Vec2::new((col * tile_w) as f32, (row * tile_h) as f32);
-                let text = Text::new(chess::from_map_coordinates(col, row), font.clone());
-
-                graphics::draw(
-                    ctx,
-                    &text,
-                    DrawParams::default().position(pos).color(GRID_COORD_COL),
-                );
-                graphics::draw(ctx, &self.tile_canvas, DrawParams::default().position(pos));
+    /// The pixel a token stands on: the centre of its cell, or exactly where
+    /// its free position is.
+    pub fn pixel(&self, token: &Token) -> (f32, f32) {
+        match token.pos {
+            Coordinates::Feet((x, y)) => {
+                let cell = FEET_PER_CELL as f32;
+                (
+                    self.offset.0 as f32 + x / cell * self.cell.0 as f32,
+                    self.offset.1 as f32 + y / cell * self.cell.1 as f32,
+                )
             }
+            _ => self.center(token.position()),
+        }
+    }
+
+    /// A pixel in feet from the top left corner of A1. Only meaningful for
+    /// square cells.
+    pub fn feet_at(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let cell = FEET_PER_CELL as f32;
+        (
+            (x - self.offset.0 as f32) / self.cell.0 as f32 * cell,
+            (y - self.offset.1 as f32) / self.cell.1 as f32 * cell,
+        )
+    }
+
+    /// Top left corner of a cell's bounding box, before rounding to pixels.
+    fn corner(&self, (col, row): (i32, i32)) -> (f32, f32) {
+        let [(step_x, _), (step_y, _)] = self.shape.layout();
+        let (w, h) = (self.cell.0 as f32, self.cell.1 as f32);
+        let (mut x, mut y) = (col as f32 * step_x * w, row as f32 * step_y * h);
+        match self.shape {
+            Shape::Hex(Orientation::Pointy) if row & 1 == 1 => x += w / 2.0,
+            Shape::Hex(Orientation::Flat) if col & 1 == 1 => y += h / 2.0,
+            _ => {}
         }
+        (self.offset.0 as f32 + x, self.offset.1 as f32 + y)
     }
 }
 
-#[derive(Debug,PartialEq)]
+impl Battlemap {
+    /// The grid on a `width`x`height` image. Without a cell size the cells
+    /// are stretched so the columns and rows fill the image.
+    pub fn grid(&self, (width, height): (u32, u32)) -> Grid {
+        let [x, y] = self.shape.layout();
+        let fit = |size: u32, offset: i32, count: i32, (step, overhang): (f32, f32)| {
+            let cells = count as f32 * step + overhang;
+            ((size as i32 - offset) as f32 / cells).round().max(1.0) as u32
+        };
+        Grid {
+            offset: self.offset,
+            cell: self.cell_size.unwrap_or_else(|| {
+                (
+                    fit(width, self.offset.0, self.columns, x),
+                    fit(height, self.offset.1, self.rows, y),
+                )
+            }),
+            shape: self.shape,
+        }
+    }
+
+    pub fn contains(&self, (col, row): (i32, i32)) -> bool {
+        col >= 0 && row >= 0 && col < self.columns && row < self.rows
+    }
+
+    /// Parse a token position and make sure it lies on this battlemap: a cell
+    /// like `C4`, or on gridless battlemaps a free position like `12ft,30ft`
+    /// or `120px,80px`. Pixels are converted to feet, which needs a cell size.
+    pub fn locate(&self, pos: &str) -> Result<Coordinates, String> {
+        let feet = match pixels(pos) {
+            Some((x, y)) => {
+                let (w, h) = self
+                    .cell_size
+                    .ok_or_else(|| format!("{} needs a battlemap --cell-size to convert pixels", pos))?;
+                let cell = FEET_PER_CELL as f32;
+                Some((
+                    (x - self.offset.0 as f32) / w as f32 * cell,
+                    (y - self.offset.1 as f32) / h as f32 * cell,
+                ))
+            }
+            None => match pos.parse::<Coordinates>() {
+                Ok(Coordinates::Feet(feet)) => Some(feet),
+                Ok(_) => None,
+                Err(e) => return Err(e.to_string()),
+            },
+        };
+        let (x, y) = match feet {
+            None => return self.cell(pos).map(Coordinates::Map),
+            Some(feet) => feet,
+        };
+        if self.shape != Shape::Gridless {
+            return Err(format!("{} is a free position, those need --grid=none", pos));
+        }
+        let (width, height) = (self.columns * FEET_PER_CELL, self.rows * FEET_PER_CELL);
+        if x < 0.0 || y < 0.0 || x >= width as f32 || y >= height as f32 {
+            return Err(format!(
+                "{} is outside of the {}x{} ft battlemap",
+                pos, width, height
+            ));
+        }
+        // a tenth of a foot is plenty and keeps the positions readable
+        let round = |v: f32| (v * 10.0).round() / 10.0;
+        Ok(Coordinates::Feet((round(x), round(y))))
+    }
+
+    /// The closest position on this battlemap.
+    pub fn clamp(&self, pos: Coordinates) -> Coordinates {
+        match pos {
+            Coordinates::Feet((x, y)) => {
+                // the far edges belong to cells that aren't there anymore
+                let (width, height) = (self.columns * FEET_PER_CELL, self.rows * FEET_PER_CELL);
+                let (max_x, max_y) = (width as f32 - 0.1, height as f32 - 0.1);
+                Coordinates::Feet((x.max(0.0).min(max_x), y.max(0.0).min(max_y)))
+            }
+            Coordinates::Map((col, row)) => {
+                Coordinates::Map((col.max(0).min(self.columns - 1), row.max(0).min(self.rows - 1)))
+            }
+            chess => match chess.to_position() {
+                Ok(cell) => self.clamp(Coordinates::Map(cell)),
+                Err(_) => chess,
+            },
+        }
+    }
+
+    /// Parse a chess coordinate and make sure it lies on this battlemap.
+    pub fn cell(&self, pos: &str) -> Result<(i32, i32), String> {
+        match Coordinates::Chess(pos.to_owned()).to_position() {
+            Ok(cell) if self.contains(cell) => Ok(cell),
+            Ok(_) => Err(format!(
+                "{} is outside of the {}x{} battlemap",
+                pos, self.columns, self.rows
+            )),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// `120px,80px`
+fn pixels(pos: &str) -> Option<(f32, f32)> {
+    let comma = pos.find(',')?;
+    let (x, y) = (&pos[..comma], &pos[comma + 1..]);
+    Some((
+        x.strip_suffix("px")?.parse().ok()?,
+        y.strip_suffix("px")?.parse().ok()?,
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub id: String,
-    pub image: String,
+    pub image: Option<String>, // a placeholder is drawn without one
     pub name: String,
     pub size: String,
     pub max_health: i32,
-    pub pos: String,
+    pub pos: Coordinates, // a cell, or feet on gridless battlemaps, see `Battlemap::locate`
     pub initiative: i32,
+    pub speed: i32, // walking, in feet per turn
+    pub fly_speed: Option<i32>,
+    pub swim_speed: Option<i32>,
+    pub climb_speed: Option<i32>,
+    pub moved: i32, // feet this turn, only counted while initiative is running
+    pub dashes: i32,
+    pub side: Option<String>,
+    pub reach: i32,        // in feet
+    pub disengaged: bool, // for this turn, moving provokes no opportunity attacks
+}
 
-    pub texture: Texture,
+/// How a token gets from one place to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Movement {
+    Walk,
+    Fly,
+    Swim,
+    Climb,
+}
+
+impl Movement {
+    /// From the `--fly`, `--swim` and `--climb` flags of a move.
+    pub fn from_flags(fly: bool, swim: bool, climb: bool) -> Movement {
+        match (fly, swim, climb) {
+            (true, _, _) => Movement::Fly,
+            (_, true, _) => Movement::Swim,
+            (_, _, true) => Movement::Climb,
+            _ => Movement::Walk,
+        }
+    }
 }
 
 impl Token {
-    pub fn new(ctx: &mut Context, id: String, image: String, name: String, size: String, max_health: i32, pos: String, initiative: i32) -> Self {
-        let texture =
-            Texture::new(ctx, image.to_owned()).expect(format!("Can't read file.").as_str());
+    /// Feet the token has left this turn, `None` if it can't move this way.
+    /// Without a swim or climb speed it swims and climbs at walking speed.
+    pub fn budget(&self, movement: Movement) -> Option<i32> {
+        let speed = match movement {
+            Movement::Walk => Some(self.speed),
+            Movement::Fly => self.fly_speed,
+            Movement::Swim => self.swim_speed.or(Some(self.speed)),
+            Movement::Climb => self.climb_speed.or(Some(self.speed)),
+        }?;
+        Some(speed * (1 + self.dashes) - self.moved)
+    }
+
+    /// Swimming or climbing without a speed for it costs an extra foot per foot.
+    fn extra_cost(&self, movement: Movement) -> i32 {
+        match movement {
+            Movement::Swim if self.swim_speed.is_none() => 1,
+            Movement::Climb if self.climb_speed.is_none() => 1,
+            _ => 0,
+        }
+    }
+
+    /// Tokens on different sides are hostile, tokens without a side are on
+    /// the same one.
+    pub fn is_hostile(&self, other: &Token) -> bool {
+        self.side != other.side
+    }
+
+    /// The cell the token stands in. Names that don't parse are off the
+    /// map, like cells left of A1.
+    pub fn position(&self) -> (i32, i32) {
+        self.pos.to_position().unwrap_or((-1, -1))
+    }
+}
+
+/// Cells hidden from the players. Nothing is hidden until the DM shadows it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fog {
+    hidden: HashSet<(i32, i32)>,
+}
+
+impl Fog {
+    pub fn is_hidden(&self, cell: (i32, i32)) -> bool {
+        self.hidden.contains(&cell)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Initiative {
+    pub round: u32,
+    pub current: Option<String>,
+}
+
+/// Something `Model::apply` has to tell the DM about a command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Note {
+    Info(String),    // what was asked for, like a distance or whose turn it is
+    Warning(String), // probably not what was meant, like a move beyond a token's speed
+}
+
+impl Note {
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Note::Warning(_))
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Note::Info(note) | Note::Warning(note) => write!(f, "{}", note),
+        }
+    }
+}
+
+/// The complete state of a game, independent of any rendering.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Model {
+    pub battlemap: Battlemap,
+    pub tokens: BTreeMap<String, Token>,
+    pub fog: Fog,
+    pub initiative: Initiative,
+    pub regions: BTreeMap<String, Vec<(i32, i32)>>,
+    pub difficult: HashSet<(i32, i32)>, // cells that cost double to move through
+    pub walls: HashSet<((i32, i32), (i32, i32))>, // between neighbours, the smaller cell first
+    pub opportunity_attacks: Vec<(String, String)>, // attacker and target, this turn
+    pub strict: bool,                   // refuse moves beyond a token's speed
+}
+
+impl Model {
+    /// Apply a command to the game state. Returns notes for the DM on
+    /// success; on error nothing was changed. Commands that don't touch the
+    /// game state (dice, help, ...) are ignored.
+    pub fn apply(&mut self, cmd: &Command) -> Result<Vec<Note>, String> {
+        use Command::*;
+        match cmd {
+            UpdateBattlemap(opts) => {
+                let bm = &self.battlemap;
+                let mut notes = vec![];
+                let mut battlemap = Battlemap {
+                    image_path: opts.url.to_owned().or_else(|| bm.image_path.to_owned()),
+                    rows: opts.rows.unwrap_or(bm.rows),
+                    columns: opts.columns.unwrap_or(bm.columns),
+                    cell_size: opts.cell_size.or(bm.cell_size),
+                    offset: (
+                        opts.offset_x.unwrap_or(bm.offset.0),
+                        opts.offset_y.unwrap_or(bm.offset.1),
+                    ),
+                    shape: opts.grid.unwrap_or(bm.shape),
+                };
+                if opts.detect_grid && battlemap.shape != Shape::Square {
+                    return Err("--detect-grid only finds square grids".into());
+                }
+                if opts.detect_grid {
+                    let found = opts
+                        .detected
+                        .ok_or("--detect-grid found no grid, the image wasn't measured")?;
+                    let grid = found.grid;
+                    notes.push(Note::Info(format!(
+                        "Detected grid: --cell-size={}x{} --offset-x={} --offset-y={} (confidence {:.0}%)",
+                        grid.cell.0,
+                        grid.cell.1,
+                        grid.offset.0,
+                        grid.offset.1,
+                        found.confidence * 100.0
+                    )));
+                    battlemap.cell_size = opts.cell_size.or(Some(grid.cell));
+                    battlemap.offset = (
+                        opts.offset_x.unwrap_or(grid.offset.0),
+                        opts.offset_y.unwrap_or(grid.offset.1),
+                    );
+                }
+                // with a fixed cell size, missing columns and rows follow from the
+                // image, but only when this command moves the cells over it
+                let moved = battlemap.cell_size != bm.cell_size
+                    || battlemap.offset != bm.offset
+                    || battlemap.image_path != bm.image_path;
+                let counted = moved && (opts.columns.is_none() || opts.rows.is_none());
+                if let (true, Some(_), Some(path)) = (counted, battlemap.cell_size, &battlemap.image_path) {
+                    match opts.image_size {
+                        Some(size) => {
+                            let (columns, rows) = battlemap.grid(size).count(size);
+                            battlemap.columns = opts.columns.unwrap_or(columns);
+                            battlemap.rows = opts.rows.unwrap_or(rows);
+                        }
+                        None => notes.push(Note::Warning(format!(
+                            "Can't count columns and rows of {}, the image can't be read",
+                            path
+                        ))),
+                    }
+                }
+                if battlemap.rows <= 0 || battlemap.columns <= 0 {
+                    return Err(format!(
+                        "battlemap needs at least one row and column, got {}x{}",
+                        battlemap.columns, battlemap.rows
+                    ));
+                }
+                self.battlemap = battlemap;
+                notes.extend(self.tokens.values().filter_map(|t| {
+                    let e = self.battlemap.locate(&t.pos.to_string()).err()?;
+                    Some(Note::Warning(format!("token {} no longer fits: {}", t.id, e)))
+                }));
+                Ok(notes)
+            }
+            UpdateToken(opts) => {
+                let mut notes = vec![];
+                let token_ref = self.tokens.get(&opts.token_id);
+                if token_ref.is_none() && opts.image.is_none() && opts.name.is_none() {
+                    if let Some(id) = commands::did_you_mean(&opts.token_id, self.tokens.keys()) {
+                        notes.push(Note::Warning(format!(
+                            "Created new token '{}'. Did you mean '{}'?",
+                            opts.token_id, id
+                        )));
+                    }
+                }
+                let pos = match (&opts.pos, &opts.step) {
+                    (Some(pos), _) if pos.starts_with('@') => Some(self.beside(&opts.token_id, &pos[1..])),
+                    (_, Some(step)) => Some(self.moved(&opts.token_id, step)),
+                    (pos, None) => pos.as_ref().map(|pos| self.place(pos)),
+                };
+                let pos = pos
+                    .transpose()
+                    .and_then(|pos| {
+                        let pos = pos.or(token_ref.map(|t| t.pos.to_string())).unwrap_or("A1".into());
+                        self.battlemap.locate(&pos)
+                    })
+                    .map_err(|e| format!("token {}: {}", opts.token_id, e))?;
+                let movement = Movement::from_flags(opts.fly, opts.swim, opts.climb);
+                let opts = opts.clone();
+                let mut token = Token {
+                    id: opts.token_id,
+                    image: opts.image.or(token_ref.and_then(|t| t.image.to_owned())),
+                    name: opts.name.or(token_ref.map(|t| t.name.to_owned())).unwrap_or("Unnamed".into()),
+                    size: opts.size.or(token_ref.map(|t| t.size.to_owned())).unwrap_or("small".into()),
+                    max_health: opts.max_health.or(token_ref.map(|t| t.max_health)).unwrap_or(10),
+                    pos,
+                    initiative: opts.initiative.or(token_ref.map(|t| t.initiative)).unwrap_or(1),
+                    speed: opts.speed.or(token_ref.map(|t| t.speed)).unwrap_or(30),
+                    fly_speed: opts.fly_speed.or(token_ref.and_then(|t| t.fly_speed)),
+                    swim_speed: opts.swim_speed.or(token_ref.and_then(|t| t.swim_speed)),
+                    climb_speed: opts.climb_speed.or(token_ref.and_then(|t| t.climb_speed)),
+                    moved: token_ref.map_or(0, |t| t.moved),
+                    dashes: token_ref.map_or(0, |t| t.dashes),
+                    side: opts.side.or(token_ref.and_then(|t| t.side.to_owned())),
+                    reach: opts.reach.or(token_ref.map(|t| t.reach)).unwrap_or(5),
+                    disengaged: token_ref.is_some_and(|t| t.disengaged),
+                };
+                // movement only counts in combat
+                let fighting = self.initiative.current.is_some();
+                let mut attacks = vec![];
+                if let Some(old) = token_ref.filter(|t| fighting && t.pos != token.pos) {
+                    let waypoints = self
+                        .waypoints(&old.pos, &token.pos)
+                        .map_err(|e| format!("token {}: {}", token.id, e))?;
+                    let cost = self.cost(&token, &waypoints, movement);
+                    let left = token
+                        .budget(movement)
+                        .ok_or_else(|| format!("token {}: can't fly without a --fly-speed", token.id))?;
+                    if cost > left && self.strict {
+                        return Err(format!(
+                            "token {}: moving costs {} ft, only {} ft are left this turn",
+                            token.id,
+                            cost,
+                            left.max(0)
+                        ));
+                    }
+                    if cost > left {
+                        notes.push(Note::Warning(format!(
+                            "{} moved {} ft with only {} ft left this turn",
+                            token.id,
+                            cost,
+                            left.max(0)
+                        )));
+                    }
+                    token.moved += cost;
+                    attacks = self.leaving_reach(&token, &waypoints);
+                }
+                notes.extend(self.flag_opportunity_attacks(&token.id, attacks));
+                self.tokens.insert(token.id.to_owned(), token);
+                Ok(notes)
+            }
+            Reveal(area) => {
+                for cell in self.cells(area)? {
+                    self.fog.hidden.remove(&cell);
+                }
+                Ok(vec![])
+            }
+            Shadow(area) => {
+                for cell in self.cells(area)? {
+                    self.fog.hidden.insert(cell);
+                }
+                Ok(vec![])
+            }
+            SetTerrain(terrain, area) => {
+                for cell in self.cells(area)? {
+                    match terrain {
+                        Terrain::Difficult => self.difficult.insert(cell),
+                        Terrain::Normal => self.difficult.remove(&cell),
+                    };
+                }
+                Ok(vec![])
+            }
+            Wall(add, a, b) => {
+                let (a, b) = (self.select(a)?, self.select(b)?);
+                let shape = self.battlemap.shape;
+                let mut count = 0;
+                for &from in &a {
+                    for to in shape.neighbours(from).into_iter().filter(|to| b.contains(to)) {
+                        let edge = (from.min(to), from.max(to));
+                        let changed = if *add { self.walls.insert(edge) } else { self.walls.remove(&edge) };
+                        count += changed as i32;
+                    }
+                }
+                match count {
+                    0 if *add => Err("No wall added, the cells must be neighbours".into()),
+                    _ => Ok(vec![]),
+                }
+            }
+            FindPath(token_id, cell) => {
+                let token = self.token(token_id)?;
+                let route = self.route(token, cell, Movement::Walk)?;
+                let cost: i32 = route.iter().map(|step| step.1).sum();
+                let cells: Vec<String> = route
+                    .iter()
+                    .map(|step| Coordinates::Map(step.0).to_string())
+                    .collect();
+                Ok(vec![Note::Info(format!("{} -> {}: {} ft via {}", token_id, cell, cost, cells.join(" ")))])
+            }
+            MoveAlongPath(opts) => {
+                let movement = Movement::from_flags(opts.fly, opts.swim, opts.climb);
+                let token = self.token(&opts.token_id)?;
+                let route = self.route(token, &opts.cell, movement)?;
+                // movement only counts in combat
+                let fighting = self.initiative.current.is_some();
+                let left = if fighting {
+                    token
+                        .budget(movement)
+                        .ok_or_else(|| format!("{} can't fly without a --fly-speed", token.id))?
+                } else {
+                    0
+                };
+                let mut notes = vec![];
+                // every cell passed and the feet spent to get there
+                let mut trail = vec![(token.position(), 0)];
+                for &(next, cost) in &route {
+                    let spent = trail[trail.len() - 1].1 + cost;
+                    if fighting && self.strict && spent > left {
+                        // allies can be passed but not stood on, back up to a free cell
+                        while trail.len() > 1 && self.token_at(trail[trail.len() - 1].0).is_some() {
+                            trail.pop();
+                        }
+                        let (cell, spent) = trail[trail.len() - 1];
+                        notes.push(Note::Info(format!(
+                            "{} stops at {}, {} ft left this turn",
+                            token.id,
+                            Coordinates::Map(cell),
+                            left - spent
+                        )));
+                        break;
+                    }
+                    trail.push((next, spent));
+                }
+                let (cell, spent) = trail[trail.len() - 1];
+                let waypoints: Vec<Coordinates> = trail.iter().map(|&(cell, _)| Coordinates::Map(cell)).collect();
+                if fighting && spent > left {
+                    notes.push(Note::Warning(format!(
+                        "{} moved {} ft with only {} ft left this turn",
+                        token.id,
+                        spent,
+                        left.max(0)
+                    )));
+                }
+                let attacks = if fighting { self.leaving_reach(token, &waypoints) } else { vec![] };
+                notes.extend(self.flag_opportunity_attacks(&opts.token_id, attacks));
+                let token = self.tokens.get_mut(&opts.token_id).expect("token was found above");
+                token.pos = Coordinates::Map(cell);
+                if fighting {
+                    token.moved += spent;
+                }
+                Ok(notes)
+            }
+            Dash(token_id) => {
+                let token = self.acting(token_id, "dashes")?;
+                let token_id = token.id.to_owned();
+                token.dashes += 1;
+                Ok(vec![Note::Info(format!(
+                    "{} dashes: {} ft left this turn",
+                    token_id,
+                    token.budget(Movement::Walk).unwrap_or(0)
+                ))])
+            }
+            Disengage(token_id) => {
+                self.acting(token_id, "disengages")?.disengaged = true;
+                Ok(vec![])
+            }
+            Strict(strict) => {
+                self.strict = *strict;
+                Ok(vec![])
+            }
+            Region(name, cells) => {
+                let mut cells = self.select(cells)?;
+                cells.sort();
+                cells.dedup();
+                let note = Note::Info(format!("Region {}: {} cells", name, cells.len()));
+                self.regions.insert(name.to_owned(), cells);
+                Ok(vec![note])
+            }
+            NextTurn => {
+                let order: Vec<String> = self.initiative_order().iter().map(|t| t.id.to_owned()).collect();
+                let current = self
+                    .initiative
+                    .current
+                    .as_ref()
+                    .and_then(|id| order.iter().position(|o| o == id));
+                let next = match current {
+                    Some(i) if i + 1 < order.len() => i + 1,
+                    _ => {
+                        if order.is_empty() {
+                            return Err("No tokens to take turns.".into());
+                        }
+                        self.initiative.round += 1;
+                        0
+                    }
+                };
+                self.initiative.current = Some(order[next].to_owned());
+                let token = self
+                    .tokens
+                    .get_mut(&order[next])
+                    .expect("the initiative order only has known tokens");
+                token.moved = 0;
+                token.dashes = 0;
+                token.disengaged = false;
+                self.opportunity_attacks.clear();
+                let token = &self.tokens[&order[next]];
+                Ok(vec![Note::Info(format!(
+                    "Round {}: {} ({})",
+                    self.initiative.round, token.name, token.id
+                ))])
+            }
+            Distance(from, to) => {
+                let locate = |s: &str| match self.tokens.get(s) {
+                    Some(token) => Ok(token.pos.clone()),
+                    // neither a cell nor a region, so probably a mistyped token
+                    None if chess::is_region_name(s) && !self.regions.contains_key(s) => {
+                        Err(self.unknown_token(s))
+                    }
+                    None => self.battlemap.locate(&self.place(s)?),
+                };
+                let (a, b) = (locate(from.as_str())?, locate(to.as_str())?);
+                if self.battlemap.shape == Shape::Gridless {
+                    // as the crow flies
+                    let ((x1, y1), (x2, y2)) = (
+                        a.to_feet().map_err(|e| e.to_string())?,
+                        b.to_feet().map_err(|e| e.to_string())?,
+                    );
+                    let feet = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+                    let feet = (feet * 10.0).round() / 10.0;
+                    return Ok(vec![Note::Info(format!("{} -> {}: {} ft", from, to, feet))]);
+                }
+                let (a, b) = (
+                    a.to_position().map_err(|e| e.to_string())?,
+                    b.to_position().map_err(|e| e.to_string())?,
+                );
+                let steps = self.battlemap.shape.distance(a, b);
+                Ok(vec![Note::Info(format!(
+                    "{} -> {}: {} ft ({} cells)",
+                    from,
+                    to,
+                    steps * FEET_PER_CELL,
+                    steps
+                ))])
+            }
+            CheckCover(attacker, target) => {
+                let cover = self.cover(attacker, target)?;
+                Ok(vec![Note::Info(format!("{} -> {}: {}", attacker, target, cover))])
+            }
+            Attack(opts) => {
+                let (attacker, target) = (&opts.attacker, &opts.target);
+                let cover = self.cover(attacker, target)?;
+                let bonus = cover
+                    .ac_bonus()
+                    .ok_or_else(|| format!("{} can't attack {}, it has total cover", attacker, target))?;
+                Ok(vec![Note::Info(match opts.ac {
+                    Some(ac) => format!("{} -> {}: {}, hit AC {}", attacker, target, cover, ac + bonus),
+                    None => format!("{} -> {}: {}", attacker, target, cover),
+                })])
+            }
+            Calibrate | Include(_) | PrintHelp(_) | Quit | Role(_) => Ok(vec![]),
+        }
+    }
+
+    /// Tokens ordered by initiative, highest first.
+    pub fn initiative_order(&self) -> Vec<&Token> {
+        let mut order: Vec<&Token> = self.tokens.values().collect();
+        order.sort_by(|a, b| b.initiative.cmp(&a.initiative).then(a.id.cmp(&b.id)));
+        order
+    }
+
+    /// The cover `target` has against attacks by `attacker`, see
+    /// `cover::cover`. Every other token stands in the way.
+    pub fn cover(&self, attacker: &str, target: &str) -> Result<Cover, String> {
+        if self.battlemap.shape != Shape::Square {
+            return Err("Cover needs a square grid".into());
+        }
+        let (attacker, target) = (self.token(attacker)?, self.token(target)?);
+        let obstacles: Vec<(i32, i32)> = self
+            .tokens
+            .values()
+            .filter(|t| t.id != attacker.id && t.id != target.id)
+            .map(Token::position)
+            .collect();
+        Ok(cover::cover(attacker.position(), target.position(), &obstacles, &self.walls))
+    }
+
+    pub fn token_at(&self, cell: (i32, i32)) -> Option<&Token> {
+        self.tokens.values().find(|t| t.position() == cell)
+    }
+
+    /// The token a turn action like dashing is for: the named one, or the
+    /// one whose turn it is.
+    fn acting(&mut self, token_id: &Option<String>, action: &str) -> Result<&mut Token, String> {
+        let token_id = token_id
+            .as_ref()
+            .or(self.initiative.current.as_ref())
+            .ok_or_else(|| format!("Nobody's turn, name the token that {}", action))?
+            .to_owned();
+        let unknown = self.unknown_token(&token_id);
+        self.tokens.get_mut(&token_id).ok_or(unknown)
+    }
+
+    /// The token called `token_id`, or an error that suggests a close one.
+    fn token(&self, token_id: &str) -> Result<&Token, String> {
+        self.tokens.get(token_id).ok_or_else(|| self.unknown_token(token_id))
+    }
+
+    fn unknown_token(&self, token_id: &str) -> String {
+        match commands::did_you_mean(token_id, self.tokens.keys()) {
+            Some(id) => format!("There is no token {}. Did you mean '{}'?", token_id, id),
+            None => format!("There is no token {}", token_id),
+        }
+    }
+
+    /// The positions a straight move from `from` to `to` passes, both
+    /// included: every cell on a grid, every started foot without one.
+    fn waypoints(&self, from: &Coordinates, to: &Coordinates) -> Result<Vec<Coordinates>, String> {
+        if self.battlemap.shape != Shape::Gridless {
+            let (a, b) = (
+                from.to_position().map_err(|e| e.to_string())?,
+                to.to_position().map_err(|e| e.to_string())?,
+            );
+            let line = self.battlemap.shape.line(a, b);
+            return Ok(std::iter::once(a).chain(line).map(Coordinates::Map).collect());
+        }
+        let ((x1, y1), (x2, y2)) = (
+            from.to_feet().map_err(|e| e.to_string())?,
+            to.to_feet().map_err(|e| e.to_string())?,
+        );
+        let n = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt().ceil() as i32;
+        Ok((0..=n)
+            .map(|i| {
+                let t = if n == 0 { 0.0 } else { i as f32 / n as f32 };
+                Coordinates::Feet((x1 + (x2 - x1) * t, y1 + (y2 - y1) * t))
+            })
+            .collect())
+    }
+
+    /// Feet it costs `token` to move along `waypoints`, see `slowdown`. Every
+    /// step after the first is a cell, or a foot on gridless battlemaps.
+    fn cost(&self, token: &Token, waypoints: &[Coordinates], movement: Movement) -> i32 {
+        let step = match self.battlemap.shape {
+            Shape::Gridless => 1,
+            _ => FEET_PER_CELL,
+        };
+        waypoints
+            .iter()
+            .skip(1)
+            .filter_map(|pos| pos.to_position().ok())
+            .map(|cell| self.slowdown(token, cell, movement) * step)
+            .sum()
+    }
+
+    /// Feet between two positions: in steps on a grid, as the crow flies
+    /// without one.
+    fn feet_between(&self, a: &Coordinates, b: &Coordinates) -> Option<f32> {
+        if self.battlemap.shape == Shape::Gridless {
+            let ((x1, y1), (x2, y2)) = (a.to_feet().ok()?, b.to_feet().ok()?);
+            return Some(((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt());
+        }
+        let steps = self.battlemap.shape.distance(a.to_position().ok()?, b.to_position().ok()?);
+        Some((steps * FEET_PER_CELL) as f32)
+    }
+
+    /// Hostile tokens whose reach `token` leaves on its way along
+    /// `waypoints`, each with where it was when it left, in the order that
+    /// happens. Leaving the same reach twice only provokes once.
+    fn leaving_reach(&self, token: &Token, waypoints: &[Coordinates]) -> Vec<(String, Coordinates)> {
+        if token.disengaged {
+            return vec![];
+        }
+        let mut attacks: Vec<(String, Coordinates)> = vec![];
+        for pair in waypoints.windows(2) {
+            for enemy in self.tokens.values().filter(|t| t.id != token.id && t.is_hostile(token)) {
+                let reaches = |pos: &Coordinates| {
+                    let feet = self.feet_between(&enemy.pos, pos);
+                    // a little slack for feet that don't add up exactly
+                    feet.is_some_and(|feet| feet <= enemy.reach as f32 + 0.01)
+                };
+                if reaches(&pair[0]) && !reaches(&pair[1]) && !attacks.iter().any(|a| a.0 == enemy.id) {
+                    attacks.push((enemy.id.to_owned(), pair[0].clone()));
+                }
+            }
+        }
+        attacks
+    }
+
+    /// Remember the opportunity attacks `token_id` provokes this turn, and
+    /// describe them for the DM. Attackers already flagged this turn don't
+    /// get another one.
+    fn flag_opportunity_attacks(&mut self, token_id: &str, attacks: Vec<(String, Coordinates)>) -> Vec<Note> {
+        let mut notes = vec![];
+        for (attacker, pos) in attacks {
+            let pair = (attacker, token_id.to_owned());
+            if !self.opportunity_attacks.contains(&pair) {
+                let note = format!("Opportunity attack: {} on {} leaving {}", pair.0, token_id, pos);
+                notes.push(Note::Info(note));
+                self.opportunity_attacks.push(pair);
+            }
+        }
+        notes
+    }
+
+    /// How many feet every foot moved in `cell` costs: double in difficult
+    /// terrain unless flying, and double again when swimming or climbing
+    /// without a speed for it.
+    fn slowdown(&self, token: &Token, cell: (i32, i32), movement: Movement) -> i32 {
+        let difficult = movement != Movement::Fly && self.difficult.contains(&cell);
+        1 + token.extra_cost(movement) + difficult as i32
+    }
 
-        Self{
-            id, image, name, size, max_health, pos, initiative, 
-            texture,
+    /// Whether a wall is in the way of a step between neighbouring cells. A
+    /// diagonal step on a square grid squeezes past a corner, so a wall
+    /// across the diagonal or touching that corner blocks it.
+    fn blocked(&self, a: (i32, i32), b: (i32, i32)) -> bool {
+        let wall = |a: (i32, i32), b: (i32, i32)| self.walls.contains(&(a.min(b), a.max(b)));
+        let diagonal = a.0 != b.0 && a.1 != b.1;
+        if !diagonal || matches!(self.battlemap.shape, Shape::Hex(_)) {
+            return wall(a, b);
         }
+        let (c, d) = ((b.0, a.1), (a.0, b.1));
+        wall(a, b) || wall(a, c) || wall(a, d) || wall(c, b) || wall(d, b)
     }
 
-    pub fn render(&self, ctx: &mut Context, bm: &Battlemap) {
-        use crate::chess::Coordinates;
+    /// The cheapest way for `token` to `cell`: around walls and through
+    /// neither hostile tokens nor cells off the battlemap, ending on a free
+    /// cell. Every step comes with what it costs in feet.
+    fn route(&self, token: &Token, cell: &str, movement: Movement) -> Result<Vec<path::Step>, String> {
+        let shape = self.battlemap.shape;
+        if shape == Shape::Gridless {
+            return Err("Paths need a grid, this battlemap has none".into());
+        }
+        let goal = self.battlemap.cell(&self.place(cell)?)?;
+        if let Some(other) = self.token_at(goal).filter(|t| t.id != token.id) {
+            return Err(format!("{} is taken by {}", cell, other.id));
+        }
+        let start = token.position();
+        let neighbours = |from: (i32, i32)| {
+            shape
+                .neighbours(from)
+                .into_iter()
+                .filter(|&to| self.battlemap.contains(to) && !self.blocked(from, to))
+                .filter(|&to| !self.tokens.values().any(|t| t.position() == to && t.is_hostile(token)))
+                .map(|to| (to, self.slowdown(token, to, movement) * FEET_PER_CELL))
+                .collect()
+        };
+        // every step costs at least one cell
+        let estimate = |from: (i32, i32)| shape.distance(from, goal) * FEET_PER_CELL;
+        let (path, _) = path::find(start, goal, neighbours, estimate)
+            .ok_or_else(|| format!("There is no way for {} to {}", token.id, cell))?;
+        Ok(path
+            .into_iter()
+            .map(|to| (to, self.slowdown(token, to, movement) * FEET_PER_CELL))
+            .collect())
+    }
 
-        let c = Coordinates::Chess(self.pos.to_owned());
-        let (col, row) = c.to_position();
+    /// Where a token ends up after a `--move`, kept on the battlemap.
+    fn moved(&self, token_id: &str, step: &str) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(token_id)
+            .ok_or_else(|| format!("{} has no position to move from yet", token_id))?;
+        let moved = chess::offset(step)
+            .and_then(|offset| token.pos.moved(offset))
+            .map_err(|e| e.to_string())?;
+        Ok(self.battlemap.clamp(moved).to_string())
+    }
 
-        let w = self.texture.width() as f32;
-        let h = self.texture.height() as f32;
+    /// Where a token would stand next to `other`: the free neighbouring cell
+    /// closest to where it stands now. On gridless battlemaps one cell away
+    /// from `other`, on the side the token comes from.
+    fn beside(&self, token_id: &str, other: &str) -> Result<String, String> {
+        let target = self.token(other)?;
+        if target.id == token_id {
+            return Err(format!("{} can't stand next to itself", token_id));
+        }
+        let current = self.tokens.get(token_id);
+        if self.battlemap.shape == Shape::Gridless {
+            let feet = |t: &Token| t.pos.to_feet().map_err(|e| e.to_string());
+            let (x, y) = feet(target)?;
+            let (dx, dy) = match current {
+                Some(token) => feet(token).map(|(cx, cy)| (cx - x, cy - y))?,
+                None => (0.0, 0.0),
+            };
+            let len = (dx * dx + dy * dy).sqrt();
+            let (dx, dy) = if len > 0.0 { (dx / len, dy / len) } else { (1.0, 0.0) };
+            let cell = FEET_PER_CELL as f32;
+            let beside = Coordinates::Feet((x + dx * cell, y + dy * cell));
+            return Ok(self.battlemap.clamp(beside).to_string());
+        }
+        let from = current.map(Token::position).unwrap_or_else(|| target.position());
+        let shape = self.battlemap.shape;
+        shape
+            .neighbours(target.position())
+            .into_iter()
+            .filter(|&cell| self.battlemap.contains(cell))
+            .filter(|&cell| self.token_at(cell).is_none_or(|t| t.id == token_id))
+            // fewest steps, then straight lines before diagonals
+            .min_by_key(|&(col, row)| {
+                let (dc, dr) = (col - from.0, row - from.1);
+                (shape.distance(from, (col, row)), dc * dc + dr * dr)
+            })
+            .map(|cell| Coordinates::Map(cell).to_string())
+            .ok_or_else(|| format!("There is no free cell next to {}", other))
+    }
+
+    fn cells(&self, area: &Area) -> Result<Vec<(i32, i32)>, String> {
+        match area {
+            Area::All => self.select(&Cells::Range(
+                (0, 0),
+                (self.battlemap.columns - 1, self.battlemap.rows - 1),
+            )),
+            Area::Cells(cells) => self.select(cells),
+        }
+    }
 
-        let (tile_w, tile_h) = bm.grid_size();
+    /// A place to stand or measure from: a region name stands for its cell
+    /// when it has exactly one, anything else is left to the caller.
+    fn place(&self, name: &str) -> Result<String, String> {
+        match self.regions.get(name).map(Vec::as_slice) {
+            Some([cell]) => Ok(Coordinates::Map(*cell).to_string()),
+            Some(cells) => Err(format!("region {} has {} cells, this needs a single one", name, cells.len())),
+            None => Ok(name.to_owned()),
+        }
+    }
 
-        let pos = Vec2::new((col * tile_w) as f32, (row * tile_h) as f32);
+    /// All cells of a selection, which must lie on the battlemap. Regions may
+    /// have been defined on a larger battlemap, so they are checked too.
+    fn select(&self, cells: &Cells) -> Result<Vec<(i32, i32)>, String> {
+        let outside = |cell: &(i32, i32)| {
+            format!(
+                "{} is outside of the {}x{} battlemap",
+                Coordinates::Map(*cell),
+                self.battlemap.columns,
+                self.battlemap.rows
+            )
+        };
+        match cells {
+            Cells::Range(a, b) => {
+                // check the corners first, a range far off the map could be huge
+                if let Some(corner) = [a, b].iter().find(|c| !self.battlemap.contains(***c)) {
+                    return Err(outside(corner));
+                }
+                let mut cells = vec![];
+                for col in a.0.min(b.0)..=a.0.max(b.0) {
+                    for row in a.1.min(b.1)..=a.1.max(b.1) {
+                        cells.push((col, row));
+                    }
+                }
+                Ok(cells)
+            }
+            Cells::List(list) => {
+                let mut cells = vec![];
+                for item in list {
+                    cells.extend(self.select(item)?);
+                }
+                Ok(cells)
+            }
+            Cells::Region(name) => {
+                let cells = self.regions.get(name).ok_or_else(|| format!("There is no region {}", name))?;
+                match cells.iter().find(|c| !self.battlemap.contains(**c)) {
+                    Some(cell) => Err(format!("region {}: {}", name, outside(cell))),
+                    None => Ok(cells.to_owned()),
+                }
+            }
+        }
+    }
+}
 
-        let uniscale = (tile_w as f32 / w).min(tile_h as f32 / h);
-        let scale = Vec2::new(uniscale, uniscale);
-        graphics::draw(ctx, &self.texture, DrawParams::default().position(pos).scale(scale));
+/// Run a whole script through the parser and the model, as the game would,
+/// and return the final state or the first error.
+#[cfg(test)]
+fn play(script: &str) -> Result<Model, String> {
+    let mut model = Model::default();
+    for cmd in commands::parse(script.into()) {
+        let mut cmd = cmd?;
+        cmd.measure_image(&model.battlemap)?;
+        model.apply(&cmd)?;
     }
-}
\ No newline at end of file
+    Ok(model)
+}
+
+/// Apply a single line to `model` the way `play` does, for the notes.
+#[cfg(test)]
+fn apply(model: &mut Model, line: &str) -> Result<Vec<String>, String> {
+    let mut cmd = commands::parse(line.into()).remove(0)?;
+    cmd.measure_image(&model.battlemap)?;
+    model.apply(&cmd).map(|notes| notes.iter().map(Note::to_string).collect())
+}
+
+#[test]
+fn test_tokens() {
+    let model = play(
+        "battlemap --url=./assets/background.jpg --columns=22 --rows=17
+         token goblinking --image=./assets/goblin.png --name=Goblin --max-health=5 --pos=A1 --initiative=11
+         token barb --name=Kuglor --size=medium --pos=D3
+         token goblinking --pos=A5",
+    )
+    .unwrap();
+
+    assert_eq!(22, model.battlemap.columns);
+    assert_eq!(2, model.tokens.len());
+    let goblin = &model.tokens["goblinking"];
+    assert_eq!("Goblin", goblin.name);
+    assert_eq!(Some("./assets/goblin.png".to_string()), goblin.image);
+    assert_eq!(None, model.tokens["barb"].image);
+    assert_eq!((0, 4), goblin.position());
+    assert_eq!(Some("barb"), model.token_at((3, 2)).map(|t| t.id.as_str()));
+    assert_eq!(None, model.token_at((0, 0)));
+}
+
+#[test]
+fn test_relative_moves() {
+    let mut model = play(
+        "battlemap --columns=8 --rows=8
+         token bob --pos=C4
+         token bob --move=+2,-1
+         token orc --pos=A1
+         token orc --move=NNE",
+    )
+    .unwrap();
+    assert_eq!("E3", model.tokens["bob"].pos.to_string());
+    assert_eq!("B1", model.tokens["orc"].pos.to_string(), "clamped to the top row");
+
+    apply(&mut model, "token orc --move=+20,+20").unwrap();
+    apply(&mut model, "token elf --pos=@orc").unwrap();
+    apply(&mut model, "token bob --pos=@orc").unwrap();
+    assert!(apply(&mut model, "token goblin --move=N").is_err());
+    assert!(apply(&mut model, "token bob --pos=@nobody").is_err());
+    assert!(apply(&mut model, "token bob --pos=@bob").is_err());
+    assert!(commands::parse("token bob --pos=C4 --move=N".into())[0].is_err());
+    assert_eq!("H8", model.tokens["orc"].pos.to_string());
+    // a new token takes the first free cell, bob the closest one to E3
+    assert_eq!("G8", model.tokens["elf"].pos.to_string());
+    assert_eq!("G7", model.tokens["bob"].pos.to_string());
+
+    let model = play(
+        "battlemap --grid=none --columns=8 --rows=8
+         token orc --pos=20ft,20ft
+         token bob --pos=0ft,20ft
+         token bob --pos=@orc
+         token orc --move=SSSSSSSSSS",
+    )
+    .unwrap();
+    assert_eq!("15ft,20ft", model.tokens["bob"].pos.to_string());
+    assert_eq!("20ft,39.9ft", model.tokens["orc"].pos.to_string());
+}
+
+#[test]
+fn test_rejects_positions_outside_of_the_battlemap() {
+    let mut model = play("battlemap --columns=5 --rows=5\ntoken bob --pos=B2").unwrap();
+    assert_eq!(
+        Err("token bob: F1 is outside of the 5x5 battlemap".to_string()),
+        apply(&mut model, "token bob --pos=F1")
+    );
+    assert_eq!("B2", model.tokens["bob"].pos.to_string());
+    assert_eq!(
+        Err("token bob: A0: rows start at 1".to_string()),
+        apply(&mut model, "token bob --pos=A0")
+    );
+    apply(&mut model, "token bob --pos=c4").unwrap();
+    assert_eq!("C4", model.tokens["bob"].pos.to_string());
+    apply(&mut model, "token bob --pos=B2").unwrap();
+
+    assert_eq!(
+        Ok(vec!["token bob no longer fits: B2 is outside of the 1x5 battlemap".to_string()]),
+        apply(&mut model, "battlemap --columns=1")
+    );
+}
+
+#[test]
+fn test_grid() {
+    // the image is 2560x1540, so 36.5 columns and 25.8 rows, half cells count
+    let model = play("battlemap --url=./assets/bg_placeholder.jpg --cell-size=70x60 --offset-x=5 --offset-y=-10").unwrap();
+    let bm = &model.battlemap;
+    let grid = bm.grid((2560, 1540));
+    assert_eq!(Grid { offset: (5, -10), cell: (70, 60), shape: Shape::Square }, grid);
+    assert_eq!((37, 26), (bm.columns, bm.rows));
+    assert_eq!((75, 50), grid.origin((1, 1)));
+    assert_eq!((1, 1), grid.cell_at((75.0, 50.0)));
+    assert_eq!((0, 0), grid.cell_at((74.9, 49.9)));
+    assert_eq!((-1, 0), grid.cell_at((4.0, 0.0)));
+
+    // explicit columns and rows win, later commands that leave the cells
+    // where they are don't count again
+    let mut model = play("battlemap --url=./assets/bg_placeholder.jpg --cell-size=70 --columns=3").unwrap();
+    assert_eq!((3, 22), (model.battlemap.columns, model.battlemap.rows));
+    assert_eq!(Some((70, 70)), model.battlemap.cell_size);
+    apply(&mut model, "battlemap --grid=square").unwrap();
+    assert_eq!((3, 22), (model.battlemap.columns, model.battlemap.rows));
+    apply(&mut model, "battlemap --cell-size=80").unwrap();
+    assert_eq!((32, 19), (model.battlemap.columns, model.battlemap.rows));
+
+    // without a cell size the cells fill the image
+    let bm = Battlemap { columns: 4, rows: 2, offset: (20, 0), ..Battlemap::default() };
+    assert_eq!((70, 50), bm.grid((300, 100)).cell);
+}
+
+#[test]
+fn test_hex_grid() {
+    let pointy = Grid { offset: (10, 0), cell: (40, 40), shape: Shape::Hex(Orientation::Pointy) };
+    assert_eq!((10, 0), pointy.origin((0, 0)));
+    assert_eq!((30, 30), pointy.origin((0, 1))); // odd rows are shifted right
+    assert_eq!((70.0, 80.0), pointy.center((1, 2)));
+    assert_eq!((1, 2), pointy.cell_at((70.0, 80.0)));
+    // the top corner of B3 sticks into the gap between A2 and B2
+    assert_eq!((1, 2), pointy.cell_at((70.0, 62.0)));
+    assert_eq!((0, 1), pointy.cell_at((67.0, 58.0)));
+    assert_eq!((4, 4), pointy.count((200, 130)));
+    assert_eq!(6, pointy.corners((0, 0)).len());
+
+    let flat = Grid { offset: (0, 0), cell: (40, 40), shape: Shape::Hex(Orientation::Flat) };
+    assert_eq!((30, 20), flat.origin((1, 0))); // odd columns are shifted down
+    assert_eq!((1, 0), flat.cell_at((31.0, 40.0)));
+    assert_eq!((0, 0), flat.cell_at((31.0, 19.0)));
+
+    // the cells fill the image, half a cell is left for the staggered rows
+    let model = play("battlemap --grid=hex-pointy --columns=4 --rows=3").unwrap();
+    let grid = model.battlemap.grid((180, 100));
+    assert_eq!((40, 40), grid.cell);
+
+    let mut model = play("battlemap --grid=hex-flat\ntoken bob --pos=A1\ntoken orc --pos=C2").unwrap();
+    assert_eq!(Ok(vec!["bob -> orc: 10 ft (2 cells)".to_string()]), apply(&mut model, "distance bob orc"));
+    assert!(play("battlemap --grid=hex-flat --url=x.png --detect-grid").is_err());
+}
+
+#[test]
+fn test_distance() {
+    let mut model = play("token bob --pos=B2").unwrap();
+    assert_eq!(
+        Ok(vec!["bob -> E6: 20 ft (4 cells)".to_string()]),
+        apply(&mut model, "distance bob E6")
+    );
+    assert!(apply(&mut model, "distance bob Z99").is_err());
+    assert_eq!(8, Shape::Square.neighbours((0, 0)).len());
+}
+
+#[test]
+fn test_gridless() {
+    let mut model = play(
+        "battlemap --grid=none --cell-size=50 --columns=10 --rows=8
+         token bob --pos=12.5ft,30ft
+         token orc --pos=175px,130px
+         token elf --pos=C4",
+    )
+    .unwrap();
+    assert_eq!("12.5ft,30ft", model.tokens["bob"].pos.to_string());
+    assert_eq!("17.5ft,13ft", model.tokens["orc"].pos.to_string());
+    assert_eq!((2, 6), model.tokens["bob"].position());
+    let grid = model.battlemap.grid((500, 400));
+    assert_eq!((125.0, 300.0), grid.pixel(&model.tokens["bob"]));
+    assert_eq!((125.0, 175.0), grid.pixel(&model.tokens["elf"]));
+    assert_eq!((12.5, 30.0), grid.feet_at((125.0, 300.0)));
+
+    assert_eq!(Ok(vec!["bob -> elf: 12.5 ft".to_string()]), apply(&mut model, "distance bob elf"));
+
+    assert_eq!(
+        Err("token bob: 50ft,0ft is outside of the 50x40 ft battlemap".to_string()),
+        apply(&mut model, "token bob --pos=50ft,0ft")
+    );
+    // free positions don't fit on a grid
+    let notes = apply(&mut model, "battlemap --grid=square").unwrap();
+    assert_eq!(2, notes.len());
+    assert!(notes[0].starts_with("token bob no longer fits"));
+
+    assert!(play("battlemap --grid=none\ntoken bob --pos=10px,10px").is_err());
+    assert!(play("token bob --pos=10ft,10ft").is_err());
+}
+
+#[test]
+fn test_detect_grid() {
+    let path = std::env::temp_dir().join(format!("dnd-token-pusher-detect-grid-{}.png", std::process::id()));
+    let grid = Grid { offset: (13, -20), cell: (40, 50), shape: Shape::Square };
+    crate::detect::gridded(400, 300, grid, 1).save(&path).unwrap();
+
+    let model = play(&format!("battlemap --url={} --detect-grid", path.display())).unwrap();
+    let bm = &model.battlemap;
+    assert_eq!((Some((40, 50)), (13, -20)), (bm.cell_size, bm.offset));
+    assert_eq!((10, 6), (bm.columns, bm.rows));
+
+    let model = play(&format!("battlemap --url={} --detect-grid --offset-x=0", path.display())).unwrap();
+    assert_eq!((0, -20), model.battlemap.offset);
+
+    assert!(play("battlemap --url=./does/not/exist.png --detect-grid").is_err());
+}
+
+#[test]
+fn test_calibrate() {
+    // dragged from bottom right to top left over B2
+    let grid = Grid::calibrate(Shape::Square, (165.2, 110.0), (95.0, 50.4)).unwrap();
+    assert_eq!(Grid { offset: (25, -10), cell: (70, 60), shape: Shape::Square }, grid);
+    assert_eq!((1, 1), grid.cell_at((100.0, 60.0)));
+    let square = Grid { offset: (0, 0), cell: (50, 50), shape: Shape::Square };
+    assert_eq!(Some(square), Grid::calibrate(Shape::Square, (100.0, 50.0), (150.0, 100.0)));
+    assert_eq!(None, Grid::calibrate(Shape::Square, (10.0, 10.0), (11.0, 40.0)));
+
+    // pointy hexes repeat every other row, 1.5 cells apart
+    let pointy = Shape::Hex(Orientation::Pointy);
+    let grid = Grid::calibrate(pointy, (100.0, 100.0), (140.0, 140.0)).unwrap();
+    assert_eq!(((20, -20), (40, 40)), (grid.offset, grid.cell));
+    assert_eq!((100, 100), grid.origin((2, 4)));
+}
+
+#[test]
+fn test_fog() {
+    let model = play("shadow\nreveal B2 C3\nreveal A1").unwrap();
+    assert!(!model.fog.is_hidden((0, 0)));
+    assert!(!model.fog.is_hidden((1, 1)));
+    assert!(!model.fog.is_hidden((2, 2)));
+    assert!(model.fog.is_hidden((3, 3)));
+    assert!(model.fog.is_hidden((19, 11)));
+
+    let model = play("shadow C3 B2").unwrap();
+    assert!(model.fog.is_hidden((1, 1)));
+    assert!(!model.fog.is_hidden((0, 0)));
+
+    assert!(play("reveal A1:U1").is_err());
+}
+
+#[test]
+fn test_regions() {
+    let mut model = play(
+        "battlemap --columns=10 --rows=10
+         region hall A1:B2,B2:C2
+         region doors J10,hall
+         shadow
+         reveal doors,E5",
+    )
+    .unwrap();
+    assert_eq!(vec![(0, 0), (0, 1), (1, 0), (1, 1), (2, 1)], model.regions["hall"]);
+    assert_eq!(6, model.regions["doors"].len());
+    assert!(!model.fog.is_hidden((9, 9)));
+    assert!(!model.fog.is_hidden((2, 1)));
+    assert!(!model.fog.is_hidden((4, 4)));
+    assert!(model.fog.is_hidden((2, 0)));
+
+    assert_eq!(Err("There is no region attic".to_string()), apply(&mut model, "reveal attic"));
+    apply(&mut model, "battlemap --columns=5").unwrap();
+    assert_eq!(
+        Err("region doors: J10 is outside of the 5x10 battlemap".to_string()),
+        apply(&mut model, "shadow doors")
+    );
+
+    // regions of a single cell work as a place
+    let mut model = play(
+        "battlemap --columns=10 --rows=10
+         region hall A1:C2
+         region throne E5
+         token bob --pos=throne",
+    )
+    .unwrap();
+    assert_eq!("E5", model.tokens["bob"].pos.to_string());
+    assert!(apply(&mut model, "move bob hall").is_err());
+    apply(&mut model, "region stairs J1").unwrap();
+    apply(&mut model, "move bob stairs --via-path").unwrap();
+    assert_eq!("J1", model.tokens["bob"].pos.to_string());
+    assert_eq!(
+        Ok(vec!["A1 -> throne: 20 ft (4 cells)".to_string()]),
+        apply(&mut model, "distance A1 throne")
+    );
+    assert_eq!(
+        Err("region hall has 6 cells, this needs a single one".to_string()),
+        apply(&mut model, "path bob hall")
+    );
+}
+
+#[test]
+fn test_initiative() {
+    let mut model = play(
+        "token barb --initiative=15
+         token goblinking --name=Goblin --initiative=11
+         token wizard --initiative=18",
+    )
+    .unwrap();
+    let order: Vec<&str> = model.initiative_order().iter().map(|t| t.id.as_str()).collect();
+    assert_eq!(vec!["wizard", "barb", "goblinking"], order);
+
+    assert_eq!(Ok(vec!["Round 1: Unnamed (wizard)".to_string()]), apply(&mut model, "next"));
+    apply(&mut model, "next").unwrap();
+    assert_eq!(Ok(vec!["Round 1: Goblin (goblinking)".to_string()]), apply(&mut model, "next"));
+    assert_eq!(Ok(vec!["Round 2: Unnamed (wizard)".to_string()]), apply(&mut model, "next"));
+    assert_eq!(Some("wizard".to_string()), model.initiative.current);
+}
+
+#[test]
+fn test_movement_budget() {
+    let mut model = play(
+        "token bob --pos=A1 --initiative=10
+         token orc --pos=H1 --initiative=5 --speed=20 --fly-speed=40
+         terrain difficult C1:C8
+         token bob --pos=E1
+         next",
+    )
+    .unwrap();
+    // moving before the fight didn't count
+    assert_eq!(0, model.tokens["bob"].moved);
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=G1"));
+    // C1 is difficult terrain
+    assert_eq!(
+        Ok(vec!["bob moved 35 ft with only 20 ft left this turn".to_string()]),
+        apply(&mut model, "token bob --pos=A1")
+    );
+    apply(&mut model, "strict on").unwrap();
+    assert_eq!(
+        Err("token bob: moving costs 5 ft, only 0 ft are left this turn".to_string()),
+        apply(&mut model, "token bob --pos=B1")
+    );
+    assert_eq!(Ok(vec!["bob dashes: 15 ft left this turn".to_string()]), apply(&mut model, "dash"));
+    apply(&mut model, "token bob --pos=B1").unwrap();
+
+    apply(&mut model, "next").unwrap();
+    assert!(apply(&mut model, "token orc --pos=C1").is_err());
+    apply(&mut model, "token orc --pos=A2 --fly").unwrap();
+    apply(&mut model, "next").unwrap();
+    assert!(apply(&mut model, "token bob --pos=A1 --fly").is_err());
+    // climbing without a climb speed costs double, and so does the terrain
+    apply(&mut model, "token bob --pos=C1 --climb").unwrap();
+    assert!(apply(&mut model, "dash nobody").is_err());
+    assert_eq!(15, model.tokens["bob"].moved);
+    assert_eq!(35, model.tokens["orc"].moved);
+    assert!(model.difficult.contains(&(2, 7)));
+}
+
+#[test]
+fn test_paths() {
+    let mut model = play(
+        "battlemap --columns=6 --rows=4
+         token bob --pos=A1 --side=party --initiative=10
+         token ally --pos=B1 --side=party
+         token orc --pos=B2
+         wall add B1:B3 C1:C3",
+    )
+    .unwrap();
+    // down past the orc, through the gap in the wall at the bottom and back up
+    let preview = apply(&mut model, "path bob D1").unwrap().remove(0);
+    assert!(preview.starts_with("bob -> D1: 35 ft via "), "{}", preview);
+    assert!(preview.ends_with(" B4 C4 D3 D2 D1") || preview.ends_with(" B4 C4 C3 C2 D1"), "{}", preview);
+    assert_eq!(Err("A1 is taken by bob".to_string()), apply(&mut model, "path orc A1"));
+    assert!(apply(&mut model, "wall add A1 C3").is_err());
+    assert!(apply(&mut model, "path nobody A1").is_err());
+
+    apply(&mut model, "next").unwrap();
+    apply(&mut model, "strict on").unwrap();
+    let notes = apply(&mut model, "move bob D1 --via-path").unwrap();
+    assert_eq!(2, notes.len());
+    assert!(notes[0].ends_with(", 0 ft left this turn"), "{}", notes[0]);
+    assert!(notes[1].starts_with("Opportunity attack: orc on bob leaving "), "{}", notes[1]);
+    apply(&mut model, "wall add B4 C4").unwrap();
+    assert!(apply(&mut model, "path ally D1").is_err());
+    apply(&mut model, "wall remove B4 C4").unwrap();
+    assert!(apply(&mut model, "path ally D1").is_ok());
+    // going straight ignores walls
+    apply(&mut model, "strict off").unwrap();
+    apply(&mut model, "move ally D1").unwrap();
+    assert_eq!("D1", model.tokens["ally"].pos.to_string());
+    assert_eq!(30, model.tokens["bob"].moved);
+    assert_ne!("D1", model.tokens["bob"].pos.to_string());
+}
+
+#[test]
+fn test_path_stops() {
+    let mut model = play(
+        "battlemap --columns=6 --rows=3
+         token bob --pos=A1 --side=party --speed=10
+         token ally --pos=C1 --side=party",
+    )
+    .unwrap();
+    // a wall across the diagonal blocks it like one touching the corner
+    assert!(apply(&mut model, "path bob B2").unwrap()[0].starts_with("bob -> B2: 5 ft via B2"));
+    apply(&mut model, "wall add A1 B2").unwrap();
+    assert!(apply(&mut model, "path bob B2").unwrap()[0].starts_with("bob -> B2: 10 ft via "));
+    // the speed for a movement only matters in combat
+    assert!(apply(&mut model, "move bob C3 --via-path --fly").is_ok());
+
+    // a corridor, so the way leads through the ally
+    let mut model = play(
+        "battlemap --columns=6 --rows=1
+         token bob --pos=A1 --side=party --speed=10
+         token ally --pos=C1 --side=party",
+    )
+    .unwrap();
+    apply(&mut model, "next").unwrap();
+    apply(&mut model, "strict on").unwrap();
+    assert!(apply(&mut model, "move bob F1 --via-path --fly").is_err());
+    // the budget runs out on the ally, so bob stays a cell short
+    let notes = apply(&mut model, "move bob F1 --via-path").unwrap();
+    assert_eq!(vec!["bob stops at B1, 5 ft left this turn".to_string()], notes);
+    assert_eq!("B1", model.tokens["bob"].pos.to_string());
+    assert_eq!(5, model.tokens["bob"].moved);
+}
+
+#[test]
+fn test_opportunity_attacks() {
+    let mut model = play(
+        "battlemap --columns=8 --rows=8
+         token bob --pos=B2 --side=party --initiative=10
+         token orc --pos=C2 --initiative=5
+         token ogre --pos=F5 --reach=10 --initiative=1",
+    )
+    .unwrap();
+    // out of combat nobody gets to attack
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=B3"));
+    apply(&mut model, "next").unwrap();
+    // moving into reach is fine, leaving it isn't
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=B2"));
+    assert_eq!(
+        Ok(vec!["Opportunity attack: orc on bob leaving B2".to_string()]),
+        apply(&mut model, "token bob --pos=A2")
+    );
+    // once per turn, even when bob comes back and leaves again
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=B2"));
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=A2"));
+    apply(&mut model, "next").unwrap();
+    // past the ogre, who is on the same side
+    assert_eq!(Ok(vec![]), apply(&mut model, "move orc H4"));
+    apply(&mut model, "next").unwrap();
+    apply(&mut model, "next").unwrap();
+    // the ogre reaches two cells
+    apply(&mut model, "dash").unwrap();
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=F3"));
+    assert_eq!(
+        Ok(vec!["Opportunity attack: ogre on bob leaving D3".to_string()]),
+        apply(&mut model, "move bob A3")
+    );
+    for _ in 0..3 {
+        apply(&mut model, "next").unwrap();
+    }
+    apply(&mut model, "disengage").unwrap();
+    apply(&mut model, "dash").unwrap();
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=F3"));
+    assert_eq!(Ok(vec![]), apply(&mut model, "token bob --pos=A3"));
+    assert!(model.tokens["bob"].disengaged);
+    assert!(model.opportunity_attacks.is_empty());
+}
+
+#[test]
+fn test_cover() {
+    let mut model = play(
+        "battlemap --columns=8 --rows=4
+         token archer --pos=A1
+         token orc --pos=E1
+         token bob --pos=C1
+         wall add D3 E3",
+    )
+    .unwrap();
+    assert_eq!(
+        Ok(vec!["archer -> orc: half cover (+2 AC)".to_string()]),
+        apply(&mut model, "cover archer orc")
+    );
+    assert_eq!(Ok(vec!["orc -> bob: no cover".to_string()]), apply(&mut model, "cover orc bob"));
+    // peeking past the end of a wall
+    apply(&mut model, "token orc --pos=E3").unwrap();
+    assert_eq!(
+        Ok(vec!["archer -> orc: three-quarters cover (+5 AC)".to_string()]),
+        apply(&mut model, "cover archer orc")
+    );
+    assert_eq!(
+        Ok(vec!["archer -> orc: three-quarters cover (+5 AC), hit AC 18".to_string()]),
+        apply(&mut model, "attack archer orc --ac=13")
+    );
+    apply(&mut model, "wall add E2 E3").unwrap();
+    assert_eq!(
+        Ok(vec!["archer -> orc: total cover (can't be targeted)".to_string()]),
+        apply(&mut model, "cover archer orc")
+    );
+    assert_eq!(
+        Err("archer can't attack orc, it has total cover".to_string()),
+        apply(&mut model, "attack archer orc --ac=13")
+    );
+    assert!(apply(&mut model, "cover archer nobody").is_err());
+    apply(&mut model, "battlemap --grid=hex-flat").unwrap();
+    assert_eq!(Err("Cover needs a square grid".to_string()), apply(&mut model, "cover archer orc"));
+}
+
+#[test]
+fn test_unknown_token_suggestion() {
+    let mut model = play("token goblinking --name=Goblin").unwrap();
+    assert_eq!(
+        Ok(vec!["Created new token 'goblinkng'. Did you mean 'goblinking'?".to_string()]),
+        apply(&mut model, "token goblinkng --pos=B2")
+    );
+
+    // commands that need an existing token suggest one too
+    let mut model = play("token goblinking --name=Goblin").unwrap();
+    for line in &[
+        "path goblinkng B2",
+        "move goblinkng B2 --via-path",
+        "distance goblinkng A1",
+        "dash goblinkng",
+        "cover goblinkng goblinking",
+        "attack goblinking goblinkng",
+    ] {
+        assert_eq!(
+            Err("There is no token goblinkng. Did you mean 'goblinking'?".to_string()),
+            apply(&mut model, line),
+            "{}",
+            line
+        );
+    }
+}
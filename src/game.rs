@@ -1,153 +1,371 @@
+use crate::chess::Coordinates;
 use crate::commands;
-use crate::domain;
+use crate::console::Console;
+use crate::domain::{Grid, Model, Shape};
+use crate::gamefile::{self, Line};
+use crate::prompt::{self, SharedVocabulary, Vocabulary};
+use crate::raster;
+use crate::view::View;
 
-use std::fs;
-use std::io;
-use std::sync::mpsc::channel;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use tetra::graphics::scaling::{ScalingMode, ScreenScaler};
-use tetra::graphics::text::Font;
 use tetra::graphics::text::Text;
+use tetra::graphics::text::VectorFontBuilder;
 use tetra::graphics::{self, Camera, Color, DrawParams};
-use tetra::input::{self, Key};
+use tetra::input::{self, Key, MouseButton};
 use tetra::math::Vec2;
 use tetra::{Context, Event, State};
 
 const MOVEMENT_SPEED: f32 = 8.0;
 const ZOOM_SPEED: f32 = 0.1;
 const BACKGROUND_COLOR: Color = Color::rgb(0.769, 0.812, 0.631);
+/// How long a frame may spend running queued commands. Whatever doesn't fit
+/// is run in the next frame, so large files don't freeze the window.
+const COMMAND_BUDGET: Duration = Duration::from_millis(12);
 
 pub struct GameState {
-    msg_chan: std::sync::mpsc::Receiver<String>,
+    msg_chan: Receiver<Result<Line, String>>,
+    loading: Arc<AtomicUsize>, // lines of the game file that haven't been run yet
+    loading_text: Text,
+    cmd_tx: Sender<Result<Line, String>>,
+    console: Option<Console>, // DM mode only
+    vocabulary: SharedVocabulary,
     scaler: ScreenScaler,
     camera: Camera,
     text: Text,
-    battlemap: domain::Battlemap,
-    token: Option<domain::Token>, // HashMap<String, domain::Token>,
+    dm_mode: bool,
+    base_dir: PathBuf, // relative paths typed at the prompt start here
+    model: Model,
+    view: View,
+    selected: Option<Selection>,
+    calibration: Option<Calibration>,
 }
 
-impl GameState {
-    pub fn new(ctx: &mut Context) -> tetra::Result<GameState> {
-        let args: Vec<String> = ::std::env::args().collect();
-        // println!("{:?}", args);
-        let dm_mode = args.contains(&String::from("--dm"));
-        let file_name = args
-            .iter()
-            .nth(1)
-            .expect("first arg need to be a game file name")
-            .to_owned();
+/// A token picked up with the mouse, remembering the cell the click started in
+/// so a release on another cell can be treated as a drag.
+struct Selection {
+    token_id: String,
+    from: (i32, i32),
+}
 
+/// Grid calibration in progress. The mouse draws a box over one grid square of
+/// the battlemap image instead of moving tokens.
+#[derive(Default)]
+struct Calibration {
+    from: Option<Vec2<f32>>, // world space corner where the drag started
+}
+
+impl GameState {
+    pub fn new(ctx: &mut Context, file_name: PathBuf, dm_mode: bool) -> tetra::Result<GameState> {
         // handle command line input in a separate thread
         // and communicate with main thread via channel
         let (tx, rx) = channel();
+        let base_dir = file_name.parent().unwrap_or_else(|| Path::new("")).to_owned();
+        let cmd_tx = tx.clone();
+        let vocabulary = SharedVocabulary::default();
+        let prompt_vocabulary = vocabulary.clone();
+        // 1 until the file has been read, so the map isn't drawn half loaded
+        let loading = Arc::new(AtomicUsize::new(1));
+        let file_loading = loading.clone();
 
         // cli thread
         thread::spawn(move || {
             // read file and produce messages
             // tried to have this in main thread but rust didn't like it
-            let file_content = fs::read_to_string(file_name);
-            // println!("file_content> {:?}", file_content);
+            let lines = gamefile::load(&file_name);
+            file_loading.store(lines.len(), Ordering::SeqCst);
 
             // tread each line as if it where typed by a person
-            for l in file_content.unwrap().lines() {
-                tx.send(l.trim().to_owned())
-                    .expect("Failed to send command to channel.");
+            for l in lines {
+                tx.send(l).expect("Failed to send command to channel.");
             }
 
-            let stdin = io::stdin();
             println!("{}", commands::HELP);
 
             // continuesly listen for new messages
-            loop {
-                let mut line_input = String::new();
-                match stdin.read_line(&mut line_input) {
-                    Ok(_bytes) => tx
-                        .send(line_input.trim().to_owned())
-                        .expect("Unable to send on channel"),
-                    Err(e) => eprintln!("Error reading input: {:?}", e),
-                }
-            }
+            prompt::run(tx, prompt_vocabulary);
         });
 
-        let text = Text::new(
-            if dm_mode { "DM Mode" } else { "Player Mode" },
-            Font::vector(ctx, "./assets/SourceCodePro-Black.ttf", 32.0)?,
-        );
+        let fonts = VectorFontBuilder::from_file_data(raster::FONT_DATA)?;
+        let font = fonts.with_size(ctx, 32.0)?;
+        let text = Text::new(if dm_mode { "DM Mode" } else { "Player Mode" }, font.clone());
+        let loading_text = Text::new("", font);
+
+        let console = if dm_mode {
+            let font = fonts.with_size(ctx, 20.0)?;
+            Some(Console::new(ctx, font)?)
+        } else {
+            None
+        };
 
         Ok(GameState {
             msg_chan: rx,
+            loading,
+            loading_text,
+            cmd_tx,
+            console,
+            vocabulary,
             text,
             scaler: ScreenScaler::with_window_size(ctx, 2048, 1920, ScalingMode::CropPixelPerfect)?,
             camera: Camera::new(2048.0, 1920.0),
-            battlemap: domain::Battlemap::new(ctx, "./assets/bg_placeholder.jpg".into(), 12, 20),
-            token: None,
+            dm_mode,
+            base_dir,
+            model: Model::default(),
+            view: View::new(ctx)?,
+            selected: None,
+            calibration: None,
         })
     }
+
+    /// Parse and run a line exactly as if it had been typed on stdin.
+    /// Errors are prefixed with the file and line number the line came from.
+    fn execute(&mut self, ctx: &mut Context, line: Line) {
+        println!("msg> {}", line.text); // debug
+        if let Some(console) = self.console.as_mut() {
+            console.log(format!("> {}", line.text));
+        }
+        let base_dir = line.dir().unwrap_or(&self.base_dir).to_owned();
+        let cmds = commands::parse(line.text);
+        for cmd in cmds {
+            // println!("Command: {:?}", cmd); // debug
+            let result = cmd.and_then(|mut c| {
+                c.resolve_paths(&base_dir);
+                c.measure_image(&self.model.battlemap)?;
+                run(ctx, self, &c)
+            });
+            match result {
+                Ok(()) => {}
+                Err(e) => match &line.location {
+                    Some(location) => self.log_error(format!("Err: {}: {}", location, e)),
+                    None => self.log_error(format!("Err: {}", e)),
+                },
+            }
+        }
+        self.update_vocabulary();
+    }
+
+    /// Share the current token ids, regions and map size with the stdin prompt.
+    fn update_vocabulary(&self) {
+        *self.vocabulary.write().unwrap() = Vocabulary {
+            token_ids: self.model.tokens.keys().cloned().collect(),
+            regions: self.model.regions.keys().cloned().collect(),
+            columns: self.model.battlemap.columns,
+            rows: self.model.battlemap.rows,
+        };
+    }
+
+    /// Print command output to stdout and the console scrollback.
+    pub fn log<S: Into<String>>(&mut self, line: S) {
+        let line = line.into();
+        println!("{}", line);
+        if let Some(console) = self.console.as_mut() {
+            console.log(line);
+        }
+    }
+
+    pub fn log_error<S: Into<String>>(&mut self, line: S) {
+        let line = line.into();
+        eprintln!("{}", line);
+        if let Some(console) = self.console.as_mut() {
+            console.log(line);
+        }
+    }
+
+    fn console_visible(&self) -> bool {
+        self.console.as_ref().is_some_and(|c| c.visible)
+    }
+
+    /// The mouse cursor in world (battlemap pixel) space.
+    fn mouse_position(&self, ctx: &Context) -> Vec2<f32> {
+        self.camera.unproject(self.scaler.mouse_position(ctx))
+    }
+
+    /// The battlemap cell currently under the mouse cursor, if any.
+    fn hovered_cell(&self, ctx: &Context) -> Option<(i32, i32)> {
+        self.view.cell_at(self.mouse_position(ctx))
+    }
+
+    /// Click a token to select it, then click a cell to move it there.
+    /// Pressing on a token and releasing over another cell drags it. Players
+    /// can't click cells hidden by fog.
+    /// Every move is emitted as a plain `token` command.
+    fn handle_mouse(&mut self, ctx: &mut Context) {
+        if self.calibration.is_some() {
+            self.handle_calibration(ctx);
+            return;
+        }
+        if input::is_mouse_button_pressed(ctx, MouseButton::Left) {
+            let cell = match self.hovered_cell(ctx) {
+                Some(cell) => cell,
+                None => {
+                    self.selected = None;
+                    return;
+                }
+            };
+            // players can't pick up what they can't see
+            if !self.dm_mode && self.model.fog.is_hidden(cell) {
+                return;
+            }
+            let clicked = self.model.token_at(cell).map(|t| t.id.to_owned());
+            match (clicked, self.selected.take()) {
+                (Some(token_id), _) => {
+                    self.selected = Some(Selection {
+                        token_id,
+                        from: cell,
+                    })
+                }
+                (None, Some(selection)) => self.move_token(ctx, &selection.token_id, cell),
+                (None, None) => {}
+            }
+        }
+
+        if input::is_mouse_button_released(ctx, MouseButton::Left) {
+            let target = self.hovered_cell(ctx);
+            let dragged = match (&self.selected, target) {
+                (Some(selection), Some(cell)) if selection.from != cell => {
+                    Some((selection.token_id.to_owned(), cell))
+                }
+                _ => None,
+            };
+            if let Some((token_id, cell)) = dragged {
+                self.selected = None;
+                self.move_token(ctx, &token_id, cell);
+            }
+        }
+    }
+
+    /// Drag a box over one grid square to derive cell size and offset. The
+    /// result is emitted as a `battlemap` command that can be pasted into the
+    /// game file.
+    fn handle_calibration(&mut self, ctx: &mut Context) {
+        let point = self.mouse_position(ctx);
+        let calibration = match self.calibration.as_mut() {
+            Some(c) => c,
+            None => return,
+        };
+        if input::is_mouse_button_pressed(ctx, MouseButton::Left) {
+            calibration.from = Some(point);
+        }
+        if !input::is_mouse_button_released(ctx, MouseButton::Left) {
+            return;
+        }
+        let from = match calibration.from.take() {
+            Some(from) => from,
+            None => return,
+        };
+        let shape = self.model.battlemap.shape;
+        match Grid::calibrate(shape, (from.x, from.y), (point.x, point.y)) {
+            Some(grid) => {
+                self.calibration = None;
+                let line = format!(
+                    "battlemap --cell-size={}x{} --offset-x={} --offset-y={}",
+                    grid.cell.0, grid.cell.1, grid.offset.0, grid.offset.1
+                );
+                self.log(format!("Grid calibrated, add this to the game file:\n{}", line));
+                self.execute(ctx, Line::interactive(line));
+            }
+            None => self.log("Too small, drag a box over a whole grid square."),
+        }
+    }
+
+    /// On gridless battlemaps the token goes exactly where the mouse is,
+    /// otherwise into `cell`.
+    fn move_token(&mut self, ctx: &mut Context, token_id: &str, cell: (i32, i32)) {
+        let free = match self.model.battlemap.shape {
+            Shape::Gridless => self.view.feet_at(self.mouse_position(ctx)),
+            _ => None,
+        };
+        let pos = match free {
+            Some((x, y)) => Coordinates::Feet((x.round(), y.round())),
+            None => Coordinates::Map(cell),
+        };
+        let line = format!("token {} --pos={}", token_id, pos);
+        self.execute(ctx, Line::interactive(line));
+    }
 }
 
-pub fn run(ctx: &mut Context, game_state: &mut GameState, cmd: &commands::Command) {
+pub fn run(ctx: &mut Context, game_state: &mut GameState, cmd: &commands::Command) -> Result<(), String> {
     use commands::Command::*;
     match cmd {
         Quit => std::process::exit(0),
-        PrintHelp(l) => println!("Unknows command: {}\n{}", l, commands::HELP),
-        Role(roller) => match roller.roll() {
-            Ok(result) => println!("-> {}", result),
-            Err(_) => {
-                eprintln!("Can't roll this: {:?}", roller)
+        Include(path) => {
+            for line in gamefile::load(Path::new(path)) {
+                match line {
+                    Ok(l) => game_state.execute(ctx, l),
+                    Err(e) => game_state.log_error(format!("Err: {}", e)),
+                }
             }
-        },
-        UpdateBattlemap(ref b_map_opts) => {
-            let bm = &game_state.battlemap;
-            game_state.battlemap = domain::Battlemap::new(
-                ctx,
-                match b_map_opts.url.to_owned() {
-                    Some(image_path) => image_path,
-                    None => bm.image_path.to_owned(),
-                },
-                b_map_opts.rows.unwrap_or(bm.rows),
-                b_map_opts.columns.unwrap_or(bm.columns),
-            );
         }
-        UpdateToken(ref token_opts) => {
-            // println!("{:?}", token_opts);
-
-            let token_opts = token_opts.clone();
-            let token_ref = game_state.token.as_ref();
-            let new_token = domain::Token::new(
-                ctx,
-                token_opts.token_id,
-                // token_opts.image.unwrap_or_else(|| token_ref.unwrap().image.to_owned()),
-                // token_opts.name.unwrap_or_else(|| token_ref.unwrap().name.to_owned()),
-                token_opts.image.or(token_ref.map(|t| t.image.to_owned())).unwrap_or("./assets/unnamed.png".into()),
-                token_opts.name.or(token_ref.map(|t| t.name.to_owned())).unwrap_or("Unnamed".into()),
-                token_opts.size.or(token_ref.map(|t| t.size.to_owned())).unwrap_or("small".into()),
-                token_opts.max_health.or(token_ref.map(|t| t.max_health)).unwrap_or(10),
-                token_opts.pos.or(token_ref.map(|t| t.pos.to_owned())).unwrap_or("A1".into()),
-                token_opts.initiative.or(token_ref.map(|t| t.initiative)).unwrap_or(1),
-            );
-
-            // lookup or create token by token_opts.token_id
-            // update fields
-            game_state.token = Some(new_token);
+        Calibrate => {
+            if game_state.calibration.take().is_some() {
+                game_state.log("Calibration cancelled.");
+            } else {
+                game_state.calibration = Some(Calibration::default());
+                game_state.log("Drag a box over a single grid square of the battlemap.");
+            }
+        }
+        PrintHelp(None) => game_state.log(commands::HELP),
+        PrintHelp(Some(command)) => game_state.log(commands::usage(command)?),
+        Role(roller) => match roller.roll() {
+            Ok(result) => game_state.log(format!("-> {}", result)),
+            Err(_) => return Err(format!("Can't roll this: {:?}", roller)),
+        },
+        UpdateBattlemap(_) | UpdateToken(_) | Reveal(_) | Shadow(_) | Region(..) | SetTerrain(..)
+        | Wall(..) | FindPath(..) | MoveAlongPath(_) | NextTurn | Dash(_) | Disengage(_) | Strict(_)
+        | Distance(..) | CheckCover(..) | Attack(_) => {
+            for note in game_state.model.apply(cmd)? {
+                game_state.log(note.to_string());
+            }
         }
     }
+    Ok(())
 }
 
 impl State for GameState {
     fn update(&mut self, ctx: &mut Context) -> tetra::Result {
-        match self.msg_chan.try_recv() {
-            Ok(msg) => {
-                println!("msg> {}", msg); // debug
-                let cmds = commands::parse(msg);
-                for cmd in cmds {
-                    // println!("Command: {:?}", cmd); // debug
-                    match cmd {
-                        Ok(c) => run(ctx, self, &c),
-                        Err(e) => println!("Err: {}", e),
-                    }
+        let start = Instant::now();
+        while start.elapsed() < COMMAND_BUDGET {
+            // only lines of the game file count towards loading, typed ones
+            // can arrive in between
+            let from_file = match self.msg_chan.try_recv() {
+                Ok(Ok(msg)) => {
+                    let from_file = msg.location.is_some();
+                    self.execute(ctx, msg);
+                    from_file
                 }
+                // only reading the game file fails
+                Ok(Err(e)) => {
+                    self.log_error(format!("Err: {}", e));
+                    true
+                }
+                Err(_) => break,
+            };
+            if from_file {
+                let _ = self
+                    .loading
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
             }
-            Err(_) => {}
+        }
+
+        // console lines take the same route as stdin lines
+        let submitted = self.console.as_mut().and_then(|c| c.update(ctx));
+        if let Some(line) = submitted {
+            self.cmd_tx
+                .send(Ok(Line::interactive(line)))
+                .expect("Unable to send on channel");
+        }
+
+        self.handle_mouse(ctx);
+
+        if self.console_visible() {
+            // keys are going to the console, don't move the camera
+            self.camera.update();
+            return Ok(());
         }
 
         if input::is_key_down(ctx, Key::W) || input::is_key_down(ctx, Key::Up) {
@@ -187,8 +405,17 @@ impl State for GameState {
         // into the renderer:
         graphics::set_transform_matrix(ctx, self.camera.as_matrix());
 
-        self.battlemap.render(ctx);
-        self.token.as_ref().map(|t| t.render(ctx, &self.battlemap));
+        let loading = self.loading.load(Ordering::SeqCst);
+        if loading == 0 {
+            self.view.render(ctx, &self.model, self.dm_mode)?;
+            for e in self.view.take_errors() {
+                self.log_error(format!("Err: {} (drawing a placeholder)", e));
+            }
+            if let Some(from) = self.calibration.as_ref().and_then(|c| c.from) {
+                let to = self.mouse_position(ctx);
+                self.view.render_box(ctx, from, to);
+            }
+        }
 
         // If you want to go back to drawing without transformations, reset the
         // matrix. This is important here, as we're going to draw more stuff
@@ -205,6 +432,21 @@ impl State for GameState {
                 .color(graphics::Color::BLUE)
                 .position(Vec2::new(16.0, 16.0)),
         );
+        if loading > 0 {
+            self.loading_text
+                .set_content(format!("Loading... {} lines left", loading));
+            graphics::draw(
+                ctx,
+                &self.loading_text,
+                DrawParams::default()
+                    .color(graphics::Color::BLUE)
+                    .position(Vec2::new(16.0, 56.0)),
+            );
+        }
+
+        if let Some(console) = self.console.as_mut() {
+            console.render(ctx);
+        }
         Ok(())
     }
 
@@ -0,0 +1,151 @@
+use crate::commands;
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a line of input came from, used to prefix error messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file.display(), self.line)
+    }
+}
+
+/// A single command line, either read from a game file or typed in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub text: String,
+    pub location: Option<Location>,
+}
+
+impl Line {
+    pub fn interactive<S: Into<String>>(text: S) -> Self {
+        Line {
+            text: text.into(),
+            location: None,
+        }
+    }
+
+    /// Directory of the file this line was read from, if any. Relative paths
+    /// on the line are resolved against it.
+    pub fn dir(&self) -> Option<&Path> {
+        self.location.as_ref().and_then(|l| l.file.parent())
+    }
+}
+
+/// Read a game file, dropping blank lines and comments and expanding
+/// `include other.game` lines. Included paths are relative to the file that
+/// includes them.
+pub fn load(path: &Path) -> Vec<Result<Line, String>> {
+    let mut lines = vec![];
+    load_into(path, None, &mut vec![], &mut lines);
+    lines
+}
+
+fn load_into(
+    path: &Path,
+    included_from: Option<&Location>,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<Result<Line, String>>,
+) {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) => {
+            let err = format!("Can't read {}: {}", path.display(), e);
+            lines.push(Err(match included_from {
+                Some(location) => format!("{}: {}", location, err),
+                None => err,
+            }));
+            return;
+        }
+    };
+    stack.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_owned()));
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    for (i, text) in content.lines().enumerate() {
+        let location = Location {
+            file: path.to_owned(),
+            line: i + 1,
+        };
+        let words = match commands::split_args(text) {
+            Ok(w) => w,
+            Err(e) => {
+                lines.push(Err(format!("{}: {}", location, e)));
+                continue;
+            }
+        };
+        match words.first().map(String::as_str) {
+            None => {} // blank line or comment
+            Some("include") => match words.len() {
+                2 => {
+                    let included = dir.join(&words[1]);
+                    let canonical = fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+                    if stack.contains(&canonical) {
+                        lines.push(Err(format!(
+                            "{}: {} includes itself",
+                            location,
+                            included.display()
+                        )));
+                    } else {
+                        load_into(&included, Some(&location), stack, lines);
+                    }
+                }
+                _ => lines.push(Err(format!("{}: usage: include <file>", location))),
+            },
+            Some(_) => lines.push(Ok(Line {
+                text: text.trim().to_owned(),
+                location: Some(location),
+            })),
+        }
+    }
+    stack.pop();
+}
+
+#[test]
+fn test_load_comments_and_includes() {
+    let dir = std::env::temp_dir().join(format!("dnd-token-pusher-gamefile-{}", std::process::id()));
+    fs::create_dir_all(dir.join("rooms")).unwrap();
+    fs::write(
+        dir.join("main.game"),
+        "# a dungeon\n\nbattlemap --columns=10\ninclude rooms/hall.game\ninclude missing.game\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("rooms/hall.game"),
+        "token bob --pos=A1 # the hero\n   \ninclude ../rooms/hall.game\n",
+    )
+    .unwrap();
+
+    let lines = load(&dir.join("main.game"));
+    assert_eq!(4, lines.len());
+    assert_eq!(
+        Ok(Line {
+            text: "battlemap --columns=10".into(),
+            location: Some(Location {
+                file: dir.join("main.game"),
+                line: 3
+            })
+        }),
+        lines[0]
+    );
+    assert_eq!(
+        Some(Location {
+            file: dir.join("rooms/hall.game"),
+            line: 1
+        }),
+        lines[1].as_ref().unwrap().location
+    );
+    assert!(lines[2].as_ref().unwrap_err().contains("includes itself"));
+    assert!(lines[3]
+        .as_ref()
+        .unwrap_err()
+        .starts_with(&format!("{}:5: Can't read", dir.join("main.game").display())));
+
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,135 @@
+/// Which way up the hexes are. Hex cells are addressed like squares, by
+/// column and row, so `C4` still reads as "third column, fourth row". Pointy
+/// topped maps shift every odd row half a cell to the right, flat topped maps
+/// shift every odd column half a cell down. Distances and neighbours are
+/// computed in cube coordinates, see https://www.redblobgames.com/grids/hexagons/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    Pointy, // rows are staggered
+    Flat,   // columns are staggered
+}
+
+const DIRECTIONS: [(i32, i32, i32); 6] = [
+    (1, -1, 0),
+    (1, 0, -1),
+    (0, 1, -1),
+    (-1, 1, 0),
+    (-1, 0, 1),
+    (0, -1, 1),
+];
+
+fn to_cube(orientation: Orientation, (col, row): (i32, i32)) -> (i32, i32, i32) {
+    let (x, z) = match orientation {
+        Orientation::Pointy => (col - (row - (row & 1)) / 2, row),
+        Orientation::Flat => (col, row - (col - (col & 1)) / 2),
+    };
+    (x, -x - z, z)
+}
+
+fn from_cube(orientation: Orientation, (x, _, z): (i32, i32, i32)) -> (i32, i32) {
+    match orientation {
+        Orientation::Pointy => (x + (z - (z & 1)) / 2, z),
+        Orientation::Flat => (x, z + (x - (x & 1)) / 2),
+    }
+}
+
+/// Number of steps between two cells.
+pub fn distance(orientation: Orientation, a: (i32, i32), b: (i32, i32)) -> i32 {
+    let (a, b) = (to_cube(orientation, a), to_cube(orientation, b));
+    (a.0 - b.0).abs().max((a.1 - b.1).abs()).max((a.2 - b.2).abs())
+}
+
+/// The cells a straight line from `a` to `b` passes through, one per step and
+/// without `a`.
+pub fn line(orientation: Orientation, a: (i32, i32), b: (i32, i32)) -> Vec<(i32, i32)> {
+    let n = distance(orientation, a, b);
+    let (a, b) = (to_cube(orientation, a), to_cube(orientation, b));
+    // nudged off the edges, so a line along an edge always picks the same side
+    let lerp = |p: i32, q: i32, t: f32, nudge: f32| p as f32 + (q - p) as f32 * t + nudge;
+    (1..=n)
+        .map(|i| {
+            let t = i as f32 / n as f32;
+            let cube = (lerp(a.0, b.0, t, 1e-6), lerp(a.1, b.1, t, 2e-6), lerp(a.2, b.2, t, -3e-6));
+            from_cube(orientation, cube_round(cube))
+        })
+        .collect()
+}
+
+/// The cube closest to a fractional one: round every axis and fix the one
+/// that was rounded the most, so they sum up to 0 again.
+fn cube_round((x, y, z): (f32, f32, f32)) -> (i32, i32, i32) {
+    let (mut rx, mut ry, mut rz) = (x.round(), y.round(), z.round());
+    let (dx, dy, dz) = ((rx - x).abs(), (ry - y).abs(), (rz - z).abs());
+    if dx > dy && dx > dz {
+        rx = -ry - rz;
+    } else if dy > dz {
+        ry = -rx - rz;
+    } else {
+        rz = -rx - ry;
+    }
+    (rx as i32, ry as i32, rz as i32)
+}
+
+/// The six cells sharing an edge with `cell`, which may be off the map.
+pub fn neighbours(orientation: Orientation, cell: (i32, i32)) -> Vec<(i32, i32)> {
+    let (x, y, z) = to_cube(orientation, cell);
+    DIRECTIONS
+        .iter()
+        .map(|(dx, dy, dz)| from_cube(orientation, (x + dx, y + dy, z + dz)))
+        .collect()
+}
+
+#[test]
+fn test_cube_round_trip() {
+    for orientation in &[Orientation::Pointy, Orientation::Flat] {
+        for col in -3..4 {
+            for row in -3..4 {
+                assert_eq!((col, row), from_cube(*orientation, to_cube(*orientation, (col, row))));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_neighbours() {
+    let mut even_row = neighbours(Orientation::Pointy, (2, 2));
+    even_row.sort();
+    assert_eq!(vec![(1, 1), (1, 2), (1, 3), (2, 1), (2, 3), (3, 2)], even_row);
+    let mut odd_row = neighbours(Orientation::Pointy, (2, 3));
+    odd_row.sort();
+    assert_eq!(vec![(1, 3), (2, 2), (2, 4), (3, 2), (3, 3), (3, 4)], odd_row);
+
+    let mut odd_col = neighbours(Orientation::Flat, (1, 1));
+    odd_col.sort();
+    assert_eq!(vec![(0, 1), (0, 2), (1, 0), (1, 2), (2, 1), (2, 2)], odd_col);
+    for n in odd_col {
+        assert_eq!(1, distance(Orientation::Flat, (1, 1), n));
+    }
+}
+
+#[test]
+fn test_line() {
+    assert_eq!(Vec::<(i32, i32)>::new(), line(Orientation::Pointy, (2, 2), (2, 2)));
+    assert_eq!(vec![(1, 0), (2, 0), (3, 0)], line(Orientation::Pointy, (0, 0), (3, 0)));
+    for orientation in &[Orientation::Pointy, Orientation::Flat] {
+        let cells = line(*orientation, (1, 1), (4, 6));
+        assert_eq!(distance(*orientation, (1, 1), (4, 6)) as usize, cells.len());
+        assert_eq!(Some(&(4, 6)), cells.last());
+        let mut from = (1, 1);
+        for cell in cells {
+            assert_eq!(1, distance(*orientation, from, cell), "{:?} -> {:?}", from, cell);
+            from = cell;
+        }
+    }
+}
+
+#[test]
+fn test_distance() {
+    assert_eq!(0, distance(Orientation::Pointy, (4, 4), (4, 4)));
+    assert_eq!(3, distance(Orientation::Pointy, (0, 0), (3, 0)));
+    // going down two rows also moves one column to the right for free
+    assert_eq!(2, distance(Orientation::Pointy, (0, 0), (1, 2)));
+    assert_eq!(3, distance(Orientation::Pointy, (0, 0), (0, 3)));
+    assert_eq!(2, distance(Orientation::Flat, (0, 0), (2, 1)));
+    assert_eq!(4, distance(Orientation::Flat, (0, 0), (0, 4)));
+}
@@ -1,20 +1,105 @@
-#![feature(try_trait)]
+#![cfg_attr(feature = "bench", feature(test))]
 
 #[macro_use]
-extern crate lazy_static; // used in chess.rs
+extern crate lazy_static; // used in chess.rs and raster.rs
+#[cfg(feature = "bench")]
+extern crate test; // benchmarks, run with `cargo +nightly bench --features bench`
 
+mod assets;
+mod check;
 mod chess;
 mod commands;
+mod console;
+mod cover;
+mod detect;
 mod domain;
 mod game;
+mod gamefile;
+mod hex;
+mod path;
+mod prompt;
+mod raster;
+mod render;
+mod view;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "A simple D&D map engine with command support.")]
+struct Opt {
+    /// Game file with the commands to start with
+    #[structopt(parse(from_os_str))]
+    game_file: Option<PathBuf>,
+
+    /// Show the DM view instead of the player view
+    #[structopt(long)]
+    dm: bool,
+
+    /// Only validate the game file, don't open a window
+    #[structopt(long)]
+    check: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Subcommand>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Subcommand {
+    /// Render the map of a game file to a PNG, without opening a window
+    Render {
+        #[structopt(parse(from_os_str))]
+        game_file: PathBuf,
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+        #[structopt(long, default_value = "player", possible_values = &["dm", "player"])]
+        view: render::View,
+    },
+}
 
 fn main() -> tetra::Result {
     use tetra::ContextBuilder;
+    let opt = Opt::from_args();
+
+    if let Some(Subcommand::Render {
+        game_file,
+        output,
+        view,
+    }) = opt.cmd
+    {
+        if let Err(e) = render::render_file(&game_file, &output, view) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+
+    let game_file = match opt.game_file {
+        Some(f) => f,
+        None => structopt::clap::Error::with_description(
+            "a game file is required",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    if opt.check {
+        let problems = check::check(&game_file);
+        for p in &problems {
+            eprintln!("{}", p);
+        }
+        if problems.is_empty() {
+            println!("{}: ok", game_file.display());
+            std::process::exit(0);
+        }
+        std::process::exit(1);
+    }
+
+    let dm_mode = opt.dm;
     ContextBuilder::new("Cameras", 640, 480)
         .resizable(true)
         .show_mouse(true)
         .quit_on_escape(true)
         .maximized(true)
         .build()?
-        .run(game::GameState::new)
+        .run(|ctx| game::GameState::new(ctx, game_file, dm_mode))
 }
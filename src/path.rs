@@ -0,0 +1,96 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// A cell and what it costs to step into it.
+pub type Step = ((i32, i32), i32);
+
+/// The cheapest way from `start` to `goal`, found with A*.
+///
+/// `neighbours` lists the cells one step away from a cell together with what
+/// it costs to enter them, `estimate` must never guess more than the real
+/// cost left from a cell to `goal`. Returns the cells after `start` up to and
+/// including `goal` and the total cost, or `None` if `goal` can't be reached.
+pub fn find<N, E>(
+    start: (i32, i32),
+    goal: (i32, i32),
+    neighbours: N,
+    estimate: E,
+) -> Option<(Vec<(i32, i32)>, i32)>
+where
+    N: Fn((i32, i32)) -> Vec<Step>,
+    E: Fn((i32, i32)) -> i32,
+{
+    let mut open = BinaryHeap::new();
+    let mut costs: HashMap<(i32, i32), i32> = HashMap::new();
+    let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+    costs.insert(start, 0);
+    open.push(Reverse((estimate(start), start)));
+
+    while let Some(Reverse((_, cell))) = open.pop() {
+        let cost = costs[&cell];
+        if cell == goal {
+            let mut path = vec![goal];
+            while let Some(previous) = came_from.get(path.last().unwrap()) {
+                path.push(*previous);
+            }
+            path.pop(); // start
+            path.reverse();
+            return Some((path, cost));
+        }
+        for (next, step) in neighbours(cell) {
+            let cost = cost + step;
+            if costs.get(&next).is_none_or(|&known| cost < known) {
+                costs.insert(next, cost);
+                came_from.insert(next, cell);
+                open.push(Reverse((cost + estimate(next), next)));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+fn square(walls: &[(i32, i32)], size: i32) -> impl Fn((i32, i32)) -> Vec<Step> + '_ {
+    move |(col, row)| {
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .map(|(dc, dr)| (col + dc, row + dr))
+            .filter(|&(c, r)| c >= 0 && r >= 0 && c < size && r < size)
+            .filter(|cell| !walls.contains(cell))
+            .map(|cell| (cell, 1))
+            .collect()
+    }
+}
+
+#[test]
+fn test_find() {
+    let manhattan = |goal: (i32, i32)| move |(c, r): (i32, i32)| (goal.0 - c).abs() + (goal.1 - r).abs();
+    assert_eq!(Some((vec![], 0)), find((1, 1), (1, 1), square(&[], 3), manhattan((1, 1))));
+    assert_eq!(
+        Some((vec![(1, 0), (2, 0)], 2)),
+        find((0, 0), (2, 0), square(&[], 3), manhattan((2, 0)))
+    );
+    // around a wall from the top down to the last row
+    let wall = [(0, 1), (1, 1), (2, 1), (3, 1)];
+    let (path, cost) = find((0, 0), (0, 2), square(&wall, 5), manhattan((0, 2))).unwrap();
+    assert_eq!(10, cost);
+    assert_eq!(10, path.len());
+    assert!(path.iter().all(|cell| !wall.contains(cell)));
+    // walled in
+    let wall = [(1, 0), (0, 1)];
+    assert_eq!(None, find((0, 0), (2, 2), square(&wall, 3), manhattan((2, 2))));
+}
+
+#[test]
+fn test_find_prefers_cheap_cells() {
+    // the middle row is a swamp, going around is cheaper than through
+    let neighbours = |(col, row): (i32, i32)| {
+        square(&[], 3)((col, row))
+            .into_iter()
+            .map(|(cell, _)| (cell, if cell.1 == 1 && cell.0 < 2 { 10 } else { 1 }))
+            .collect()
+    };
+    let (path, cost) = find((0, 0), (0, 2), neighbours, |_| 0).unwrap();
+    assert_eq!(6, cost);
+    assert_eq!(vec![(1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)], path);
+}
@@ -0,0 +1,188 @@
+use crate::chess::Coordinates;
+use crate::commands;
+use crate::gamefile::Line;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use rustyline_derive::{Helper, Highlighter, Hinter, Validator};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, RwLock};
+
+const HISTORY_FILE: &str = ".dnd-token-pusher_history";
+
+/// Everything the prompt can complete besides command names. Kept up to date
+/// by the game loop, read by the stdin thread.
+#[derive(Debug, Default, Clone)]
+pub struct Vocabulary {
+    pub token_ids: Vec<String>,
+    pub regions: Vec<String>,
+    pub columns: i32,
+    pub rows: i32,
+}
+
+pub type SharedVocabulary = Arc<RwLock<Vocabulary>>;
+
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct PromptHelper {
+    vocabulary: SharedVocabulary,
+}
+
+impl Completer for PromptHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let vocabulary = self.vocabulary.read().unwrap();
+        Ok(complete(&vocabulary, line, pos))
+    }
+}
+
+/// Returns the start of the word under the cursor and all candidates for it.
+fn complete(vocabulary: &Vocabulary, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let start = line[..pos]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| c.is_whitespace())
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let word = &line[start..pos];
+
+    if start == 0 {
+        let candidates = commands::names()
+            .into_iter()
+            .filter(|n| n.starts_with(word))
+            .map(String::from)
+            .collect();
+        return (start, candidates);
+    }
+
+    // complete the value of a --pos=... flag
+    if let Some(eq) = word.find('=') {
+        let (flag, value) = word.split_at(eq + 1);
+        let candidates = match (flag, value.strip_prefix('@')) {
+            ("--pos=", Some(id)) => vocabulary
+                .token_ids
+                .iter()
+                .filter(|t| t.starts_with(id))
+                .map(|t| format!("@{}", t))
+                .collect(),
+            ("--pos=", None) => coordinates(vocabulary, value),
+            _ => vec![],
+        };
+        return (start + flag.len(), candidates);
+    }
+
+    let mut candidates: Vec<String> = vocabulary
+        .token_ids
+        .iter()
+        .chain(&vocabulary.regions)
+        .filter(|id| id.starts_with(word))
+        .cloned()
+        .collect();
+    if !word.is_empty() {
+        candidates.extend(coordinates(vocabulary, word));
+    }
+    (start, candidates)
+}
+
+fn coordinates(vocabulary: &Vocabulary, prefix: &str) -> Vec<String> {
+    let prefix = prefix.to_uppercase();
+    let mut coords = vec![];
+    for col in 0..vocabulary.columns {
+        for row in 0..vocabulary.rows {
+            let c = Coordinates::Map((col, row)).to_string();
+            if c.starts_with(&prefix) {
+                coords.push(c);
+            }
+        }
+    }
+    coords
+}
+
+fn history_path() -> PathBuf {
+    match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home).join(HISTORY_FILE),
+        None => PathBuf::from(HISTORY_FILE),
+    }
+}
+
+/// Read lines from stdin with history and tab completion until stdin closes.
+pub fn run(tx: Sender<Result<Line, String>>, vocabulary: SharedVocabulary) {
+    let mut editor = Editor::<PromptHelper>::new();
+    editor.set_helper(Some(PromptHelper { vocabulary }));
+
+    let history = history_path();
+    // there is no history on the very first run
+    let _ = editor.load_history(&history);
+
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                let line = line.trim().to_owned();
+                if !line.is_empty() {
+                    editor.add_history_entry(line.as_str());
+                    if let Err(e) = editor.save_history(&history) {
+                        eprintln!("Can't save history to {:?}: {}", history, e);
+                    }
+                }
+                tx.send(Ok(Line::interactive(line)))
+                    .expect("Unable to send on channel");
+            }
+            // Ctrl-C and Ctrl-D end the game, not just the prompt
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+                tx.send(Ok(Line::interactive("quit")))
+                    .expect("Unable to send on channel");
+                break;
+            }
+            Err(e) => {
+                eprintln!("Error reading input: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_complete_command_names() {
+    let vocabulary = Vocabulary::default();
+    assert_eq!((0, vec!["token".to_string()]), complete(&vocabulary, "to", 2));
+    assert_eq!(
+        (0, vec!["q".to_string(), "quit".to_string()]),
+        complete(&vocabulary, "q", 1)
+    );
+    // whitespace can be longer than a byte
+    assert_eq!(7, complete(&vocabulary, "token\u{a0}bo", 9).0);
+}
+
+#[test]
+fn test_complete_token_ids_and_coordinates() {
+    let vocabulary = Vocabulary {
+        token_ids: vec!["barb".into(), "goblinking".into()],
+        regions: vec!["bridge".into()],
+        columns: 3,
+        rows: 12,
+    };
+    assert_eq!(
+        (6, vec!["goblinking".to_string()]),
+        complete(&vocabulary, "token gob", 9)
+    );
+    assert_eq!(
+        (17, vec!["B1".to_string(), "B10".to_string(), "B11".to_string(), "B12".to_string()]),
+        complete(&vocabulary, "token barb --pos=b1", 19)
+    );
+    assert_eq!(
+        (7, vec!["bridge".to_string()]),
+        complete(&vocabulary, "reveal br", 9)
+    );
+    assert_eq!(
+        (17, vec!["@goblinking".to_string()]),
+        complete(&vocabulary, "token barb --pos=@g", 19)
+    );
+    // outside of the battlemap
+    assert_eq!((17, vec![]), complete(&vocabulary, "token barb --pos=D1", 19));
+}
@@ -0,0 +1,337 @@
+use crate::chess::Coordinates;
+use crate::domain::{Grid, Shape};
+
+use image::{imageops, Pixel, Rgba, RgbaImage};
+use rusttype::{point, Font, Scale};
+
+const PLACEHOLDER_MAP_CELL: u32 = 70;
+const PLACEHOLDER_MAP_COL: Rgba<u8> = Rgba([200, 200, 200, 255]);
+const PLACEHOLDER_MAP_ALT_COL: Rgba<u8> = Rgba([170, 170, 170, 255]);
+const PLACEHOLDER_TOKEN_SIZE: u32 = 128;
+const PLACEHOLDER_TEXT_COL: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const GRID_EDGE_WIDTH: u32 = 3;
+const GRID_EDGE_COL: Rgba<u8> = Rgba([128, 128, 128, 128]);
+const GRID_COORD_COL: Rgba<u8> = Rgba([0, 0, 0, 64]);
+const GRID_FONT_SIZE: f32 = 32.0;
+
+/// Shipped with the binary so text can be drawn whatever the working directory.
+pub const FONT_DATA: &[u8] = include_bytes!("../assets/SourceCodePro-Black.ttf");
+
+lazy_static! {
+    pub static ref FONT: Font<'static> =
+        Font::try_from_bytes(FONT_DATA).expect("Bundled font is broken.");
+}
+
+/// Load an image from disk. The error names the path.
+pub fn load_image(path: &str) -> Result<RgbaImage, String> {
+    image::open(path)
+        .map(|i| i.to_rgba8())
+        .map_err(|e| format!("Can't load image {}: {}", path, e))
+}
+
+/// Alpha blend a rectangle onto the image, clipped to its bounds.
+pub fn fill_rect(img: &mut RgbaImage, x: i32, y: i32, width: u32, height: u32, color: Rgba<u8>) {
+    let clip = |from: i32, len: u32, max: u32| {
+        let to = (from as i64 + len as i64).min(max as i64).max(0) as u32;
+        (from.max(0) as u32).min(to)..to
+    };
+    for py in clip(y, height, img.height()) {
+        for px in clip(x, width, img.width()) {
+            img.get_pixel_mut(px, py).blend(&color);
+        }
+    }
+}
+
+/// Alpha blend `top` onto the image with its top left corner at `(x, y)`,
+/// which may be outside of the image.
+pub fn overlay(img: &mut RgbaImage, top: &RgbaImage, x: i32, y: i32) {
+    let (skip_x, skip_y) = ((-x).max(0) as u32, (-y).max(0) as u32);
+    if skip_x >= top.width() || skip_y >= top.height() {
+        return;
+    }
+    let (width, height) = (top.width() - skip_x, top.height() - skip_y);
+    let visible = imageops::crop_imm(top, skip_x, skip_y, width, height);
+    imageops::overlay(img, &visible, x.max(0) as u32, y.max(0) as u32);
+}
+
+/// Width in pixels of a line of text.
+pub fn text_width(text: &str, size: f32) -> f32 {
+    FONT.layout(text, Scale::uniform(size), point(0.0, 0.0))
+        .last()
+        .map_or(0.0, |g| g.position().x + g.unpositioned().h_metrics().advance_width)
+}
+
+/// Draw a line of text with its top left corner at `(x, y)`.
+pub fn draw_text(img: &mut RgbaImage, text: &str, x: f32, y: f32, size: f32, color: Rgba<u8>) {
+    let scale = Scale::uniform(size);
+    let ascent = FONT.v_metrics(scale).ascent;
+    for glyph in FONT.layout(text, scale, point(x, y + ascent)) {
+        if let Some(bb) = glyph.pixel_bounding_box() {
+            glyph.draw(|gx, gy, coverage| {
+                let px = bb.min.x + gx as i32;
+                let py = bb.min.y + gy as i32;
+                if px < 0 || py < 0 || px as u32 >= img.width() || py as u32 >= img.height() {
+                    return;
+                }
+                let mut c = color;
+                c[3] = (color[3] as f32 * coverage) as u8;
+                img.get_pixel_mut(px as u32, py as u32).blend(&c);
+            });
+        }
+    }
+}
+
+/// Stand-in for a token without a usable image: a coloured circle with the
+/// initials of its name. The colour is derived from the id so every token
+/// keeps its colour.
+pub fn token_placeholder(id: &str, name: &str) -> RgbaImage {
+    let size = PLACEHOLDER_TOKEN_SIZE;
+    let hash = id.bytes().fold(5381u32, |h, b| h.wrapping_mul(33) ^ b as u32);
+    let color = Rgba([
+        64 + (hash % 160) as u8,
+        64 + ((hash >> 8) % 160) as u8,
+        64 + ((hash >> 16) % 160) as u8,
+        255,
+    ]);
+
+    let mut img = RgbaImage::new(size, size);
+    let r = size as f32 / 2.0;
+    for (x, y, px) in img.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - r, y as f32 + 0.5 - r);
+        if dx * dx + dy * dy <= r * r {
+            *px = color;
+        }
+    }
+
+    let text = initials(name);
+    let text_size = size as f32 / 2.0;
+    let x = (size as f32 - text_width(&text, text_size)) / 2.0;
+    let y = (size as f32 - text_size) / 2.0;
+    draw_text(&mut img, &text, x, y, text_size, PLACEHOLDER_TEXT_COL);
+    img
+}
+
+/// Stand-in for a battlemap without a usable image: a checkerboard with one
+/// square per cell.
+pub fn battlemap_placeholder(columns: i32, rows: i32) -> RgbaImage {
+    let cell = PLACEHOLDER_MAP_CELL;
+    RgbaImage::from_fn(columns.max(1) as u32 * cell, rows.max(1) as u32 * cell, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) {
+            PLACEHOLDER_MAP_COL
+        } else {
+            PLACEHOLDER_MAP_ALT_COL
+        }
+    })
+}
+
+/// Transparent image with the cell edges and coordinate labels, to be drawn
+/// on top of a `width`x`height` battlemap. Expensive for large maps, so it is
+/// built once per battlemap.
+pub fn grid_overlay(width: u32, height: u32, grid: Grid, columns: i32, rows: i32) -> RgbaImage {
+    let mut img = RgbaImage::new(width, height);
+    let (tile_w, tile_h) = grid.cell;
+    let edge = GRID_EDGE_WIDTH.min(tile_w).min(tile_h);
+    match grid.shape {
+        Shape::Square => {}
+        Shape::Hex(_) => {
+            hex_overlay(&mut img, grid, columns, rows, edge);
+            return img;
+        }
+        Shape::Gridless => return img,
+    }
+    for col in 0..columns {
+        for row in 0..rows {
+            let (x, y) = grid.origin((col, row));
+            let label = Coordinates::Map((col, row)).to_string();
+            draw_text(&mut img, &label, x as f32, y as f32, GRID_FONT_SIZE, GRID_COORD_COL);
+            let (right, bottom) = (x + (tile_w - edge) as i32, y + (tile_h - edge) as i32);
+            fill_rect(&mut img, right, y, edge, tile_h, GRID_EDGE_COL);
+            fill_rect(&mut img, x, bottom, tile_w, edge, GRID_EDGE_COL);
+            // the image border frames the grid unless it is offset
+            if col == 0 && grid.offset.0 > 0 {
+                fill_rect(&mut img, x, y, edge, tile_h, GRID_EDGE_COL);
+            }
+            if row == 0 && grid.offset.1 > 0 {
+                fill_rect(&mut img, x, y, tile_w, edge, GRID_EDGE_COL);
+            }
+        }
+    }
+    img
+}
+
+/// Hexes share their edges, so the edges are collected in a mask first and
+/// blended once. Blending every outline on its own would draw shared edges
+/// darker than the outer ones.
+fn hex_overlay(img: &mut RgbaImage, grid: Grid, columns: i32, rows: i32, edge: u32) {
+    let (width, height) = img.dimensions();
+    let mut mask = vec![false; (width * height) as usize];
+    let mut stamp = |x: f32, y: f32| {
+        let (x, y) = ((x - edge as f32 / 2.0).round() as i32, (y - edge as f32 / 2.0).round() as i32);
+        for py in y.max(0)..(y + edge as i32).min(height as i32) {
+            for px in x.max(0)..(x + edge as i32).min(width as i32) {
+                mask[(py as u32 * width + px as u32) as usize] = true;
+            }
+        }
+    };
+    for col in 0..columns {
+        for row in 0..rows {
+            let corners = grid.corners((col, row));
+            for (i, &(x1, y1)) in corners.iter().enumerate() {
+                let (x2, y2) = corners[(i + 1) % corners.len()];
+                let steps = (x2 - x1).abs().max((y2 - y1).abs()).ceil().max(1.0);
+                for step in 0..=steps as u32 {
+                    let t = step as f32 / steps;
+                    stamp(x1 + (x2 - x1) * t, y1 + (y2 - y1) * t);
+                }
+            }
+        }
+    }
+
+    for col in 0..columns {
+        for row in 0..rows {
+            let label = Coordinates::Map((col, row)).to_string();
+            let (x, _) = grid.center((col, row));
+            let (_, y) = grid.origin((col, row));
+            let x = x - text_width(&label, GRID_FONT_SIZE) / 2.0;
+            let y = y as f32 + grid.cell.1 as f32 / 5.0;
+            draw_text(img, &label, x, y, GRID_FONT_SIZE, GRID_COORD_COL);
+        }
+    }
+    for (i, px) in img.pixels_mut().enumerate() {
+        if mask[i] {
+            px.blend(&GRID_EDGE_COL);
+        }
+    }
+}
+
+/// White where a cell of the grid covers its bounding box, transparent
+/// elsewhere. Tinted to draw fog over a single cell.
+pub fn cell_mask(grid: Grid) -> RgbaImage {
+    let cell = Grid { offset: (0, 0), ..grid };
+    let corners = cell.corners((0, 0));
+    // inside a convex outline every edge turns the same way around the point
+    let inside = |x: f32, y: f32| {
+        corners.iter().enumerate().all(|(i, &(x1, y1))| {
+            let (x2, y2) = corners[(i + 1) % corners.len()];
+            (x2 - x1) * (y - y1) - (y2 - y1) * (x - x1) >= 0.0
+        })
+    };
+    RgbaImage::from_fn(grid.cell.0, grid.cell.1, |x, y| {
+        if inside(x as f32 + 0.5, y as f32 + 0.5) {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    })
+}
+
+/// Alpha blend `color` onto the image wherever the mask isn't transparent,
+/// with the mask's top left corner at `(x, y)`.
+pub fn fill_mask(img: &mut RgbaImage, mask: &RgbaImage, x: i32, y: i32, color: Rgba<u8>) {
+    for (mx, my, m) in mask.enumerate_pixels() {
+        let (px, py) = (x + mx as i32, y + my as i32);
+        if m[3] > 0 && px >= 0 && py >= 0 && (px as u32) < img.width() && (py as u32) < img.height() {
+            img.get_pixel_mut(px as u32, py as u32).blend(&color);
+        }
+    }
+}
+
+fn initials(name: &str) -> String {
+    name.split_whitespace()
+        .filter_map(|w| w.chars().next())
+        .take(2)
+        .flat_map(char::to_uppercase)
+        .collect()
+}
+
+#[test]
+fn test_initials() {
+    assert_eq!("GK", initials("goblin king"));
+    assert_eq!("KT", initials("Kuglor the Barbarian"));
+    assert_eq!("U", initials("Unnamed"));
+    assert_eq!("", initials(""));
+}
+
+#[test]
+fn test_placeholders() {
+    let token = token_placeholder("goblinking", "Goblin King");
+    assert_eq!((128, 128), token.dimensions());
+    // transparent corners, filled centre
+    assert_eq!(0, token.get_pixel(0, 0)[3]);
+    assert_eq!(255, token.get_pixel(64, 20)[3]);
+    assert_eq!(token, token_placeholder("goblinking", "Goblin King"));
+
+    let map = battlemap_placeholder(3, 2);
+    assert_eq!((210, 140), map.dimensions());
+    assert_ne!(map.get_pixel(0, 0), map.get_pixel(70, 0));
+
+    assert!(load_image("./does/not/exist.png")
+        .unwrap_err()
+        .starts_with("Can't load image ./does/not/exist.png"));
+}
+
+#[test]
+fn test_grid_overlay() {
+    let cells = Grid { offset: (0, 0), cell: (100, 100), shape: Shape::Square };
+    let grid = grid_overlay(300, 200, cells, 3, 2);
+    assert_eq!((300, 200), grid.dimensions());
+    assert_eq!(0, grid.get_pixel(50, 50)[3]);
+    // right and bottom edge of the first cell
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(99, 50));
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(50, 99));
+    // label in the top left corner
+    assert!((0..30).any(|x| (0..30).any(|y| grid.get_pixel(x, y)[3] > 0)));
+
+    // offset, non-square cells partially off the image
+    let cells = Grid { offset: (20, -10), cell: (90, 60), shape: Shape::Square };
+    let grid = grid_overlay(300, 200, cells, 3, 4);
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(20, 20));
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(109, 20));
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(60, 49));
+    assert_eq!(0, grid.get_pixel(10, 20)[3]);
+}
+
+#[test]
+fn test_hex_overlay() {
+    use crate::hex::Orientation;
+    let cells = Grid { offset: (0, 0), cell: (80, 80), shape: Shape::Hex(Orientation::Pointy) };
+    let grid = grid_overlay(300, 200, cells, 3, 2);
+    // left edge of A1, shared by nothing, and the edge shared by A1 and B1
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(0, 40));
+    assert_eq!(GRID_EDGE_COL, *grid.get_pixel(80, 40));
+    // inside A1, below its label
+    assert_eq!(0, grid.get_pixel(40, 65)[3]);
+
+    let mask = cell_mask(cells);
+    assert_eq!((80, 80), mask.dimensions());
+    assert_eq!(0, mask.get_pixel(2, 2)[3]);
+    assert_eq!(255, mask.get_pixel(40, 2)[3]);
+    assert_eq!(255, mask.get_pixel(2, 40)[3]);
+    let square = cell_mask(Grid { shape: Shape::Square, ..cells });
+    assert!(square.pixels().all(|p| p[3] == 255));
+    let gridless = grid_overlay(300, 200, Grid { shape: Shape::Gridless, ..cells }, 3, 2);
+    assert!(gridless.pixels().all(|p| p[3] == 0));
+}
+
+#[test]
+fn test_clipping() {
+    let mut img = RgbaImage::new(10, 10);
+    fill_rect(&mut img, -5, 8, 7, 7, PLACEHOLDER_TEXT_COL);
+    assert_eq!(PLACEHOLDER_TEXT_COL, *img.get_pixel(1, 9));
+    assert_eq!(0, img.get_pixel(2, 9)[3]);
+    assert_eq!(0, img.get_pixel(1, 7)[3]);
+
+    let mut img = RgbaImage::new(10, 10);
+    overlay(&mut img, &RgbaImage::from_pixel(4, 4, PLACEHOLDER_TEXT_COL), -2, -3);
+    assert_eq!(PLACEHOLDER_TEXT_COL, *img.get_pixel(1, 0));
+    assert_eq!(0, img.get_pixel(2, 0)[3]);
+    assert_eq!(0, img.get_pixel(0, 1)[3]);
+    overlay(&mut img, &RgbaImage::from_pixel(4, 4, PLACEHOLDER_TEXT_COL), -4, 0);
+}
+
+#[cfg(all(test, feature = "bench"))]
+#[bench]
+fn bench_grid_overlay_100x100(b: &mut test::Bencher) {
+    let cells = Grid { offset: (0, 0), cell: (30, 30), shape: Shape::Square };
+    b.iter(|| grid_overlay(3000, 3000, cells, 100, 100));
+}
@@ -0,0 +1,214 @@
+use crate::commands;
+use crate::domain::Model;
+use crate::gamefile;
+use crate::raster::{self, fill_mask, overlay};
+
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+const FOG_PLAYER_COL: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const FOG_DM_COL: Rgba<u8> = Rgba([0, 0, 0, 128]);
+
+/// Which side of the screen to render.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum View {
+    Dm,
+    Player,
+}
+
+impl std::str::FromStr for View {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dm" => Ok(View::Dm),
+            "player" => Ok(View::Player),
+            _ => Err(format!("unknown view {}, expected dm or player", s)),
+        }
+    }
+}
+
+/// Replay a game file and write the resulting map to `output` as a PNG.
+/// Lines that fail are reported on stderr and skipped, like the game does;
+/// only an image that can't be written is an error.
+pub fn render_file(game_file: &Path, output: &Path, view: View) -> Result<(), String> {
+    let mut model = Model::default();
+    for line in gamefile::load(game_file) {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        let base_dir = line.dir().unwrap_or_else(|| Path::new("")).to_owned();
+        let location = line.location;
+        for cmd in commands::parse(line.text) {
+            let applied = cmd.and_then(|mut cmd| {
+                cmd.resolve_paths(&base_dir);
+                cmd.measure_image(&model.battlemap)?;
+                model.apply(&cmd)
+            });
+            match (applied, &location) {
+                (Err(e), Some(location)) => eprintln!("{}: {}", location, e),
+                (Err(e), None) => eprintln!("{}", e),
+                (Ok(_), _) => {}
+            }
+        }
+    }
+
+    let (img, warnings) = render(&model, view);
+    for w in warnings {
+        eprintln!("{}", w);
+    }
+    img.save(output)
+        .map_err(|e| format!("Can't write {}: {}", output.display(), e))
+}
+
+/// Composite battlemap, grid, coordinate labels, tokens and fog the same way
+/// the game window does. Images that can't be loaded are replaced by
+/// placeholders and reported in the returned warnings.
+pub fn render(model: &Model, view: View) -> (RgbaImage, Vec<String>) {
+    let mut warnings = vec![];
+    let bm = &model.battlemap;
+    let mut img = match &bm.image_path {
+        Some(path) => raster::load_image(path).unwrap_or_else(|e| {
+            warnings.push(e);
+            raster::battlemap_placeholder(bm.columns, bm.rows)
+        }),
+        None => raster::battlemap_placeholder(bm.columns, bm.rows),
+    };
+    let (width, height) = img.dimensions();
+    let cells = bm.grid((width, height));
+    let (tile_w, tile_h) = cells.cell;
+    let grid = raster::grid_overlay(width, height, cells, bm.columns, bm.rows);
+    imageops::overlay(&mut img, &grid, 0, 0);
+
+    for token in model.tokens.values() {
+        let (col, row) = token.position();
+        if view == View::Player && model.fog.is_hidden((col, row)) {
+            continue;
+        }
+        let texture = match &token.image {
+            Some(path) => raster::load_image(path).unwrap_or_else(|e| {
+                warnings.push(e);
+                raster::token_placeholder(&token.id, &token.name)
+            }),
+            None => raster::token_placeholder(&token.id, &token.name),
+        };
+        let uniscale = (tile_w as f32 / texture.width() as f32)
+            .min(tile_h as f32 / texture.height() as f32);
+        let scaled = imageops::resize(
+            &texture,
+            ((texture.width() as f32 * uniscale) as u32).max(1),
+            ((texture.height() as f32 * uniscale) as u32).max(1),
+            FilterType::Triangle,
+        );
+        let (x, y) = cells.pixel(token);
+        let x = (x - scaled.width() as f32 / 2.0).round() as i32;
+        let y = (y - scaled.height() as f32 / 2.0).round() as i32;
+        overlay(&mut img, &scaled, x, y);
+    }
+
+    let fog = match view {
+        View::Dm => FOG_DM_COL,
+        View::Player => FOG_PLAYER_COL,
+    };
+    let mask = raster::cell_mask(cells);
+    for col in 0..bm.columns {
+        for row in 0..bm.rows {
+            if model.fog.is_hidden((col, row)) {
+                let (x, y) = cells.origin((col, row));
+                fill_mask(&mut img, &mask, x, y, fog);
+            }
+        }
+    }
+
+    (img, warnings)
+}
+
+#[test]
+fn test_render() {
+    let mut model = Model::default();
+    for cmd in commands::parse(
+        "battlemap --url=./assets/bg_placeholder.jpg --columns=4 --rows=2\nshadow D2".into(),
+    ) {
+        model.apply(&cmd.unwrap()).unwrap();
+    }
+    let background = raster::load_image("./assets/bg_placeholder.jpg").unwrap();
+
+    let (player, warnings) = render(&model, View::Player);
+    assert!(warnings.is_empty());
+    assert_eq!(background.dimensions(), player.dimensions());
+    let (w, h) = player.dimensions();
+    // the last cell is hidden from players, but only dimmed for the DM
+    assert_eq!(&Rgba([0, 0, 0, 255]), player.get_pixel(w - 10, h - 10));
+    let (dm, _) = render(&model, View::Dm);
+    assert_ne!(&Rgba([0, 0, 0, 255]), dm.get_pixel(w - 10, h - 10));
+    // grid lines on the right edge of the first cell
+    let tile_w = (w as f64 / 4.0).round() as u32;
+    assert_ne!(background.get_pixel(tile_w - 1, h / 4), player.get_pixel(tile_w - 1, h / 4));
+}
+
+#[test]
+fn test_render_offset_grid() {
+    let mut model = Model::default();
+    for cmd in commands::parse(
+        "battlemap --cell-size=50x40 --offset-x=20 --offset-y=-10 --columns=3 --rows=2\nshadow A1".into(),
+    ) {
+        model.apply(&cmd.unwrap()).unwrap();
+    }
+    let (img, _) = render(&model, View::Player);
+    // A1 is shifted right and partly above the image
+    assert_eq!(&Rgba([0, 0, 0, 255]), img.get_pixel(20, 0));
+    assert_eq!(&Rgba([0, 0, 0, 255]), img.get_pixel(69, 29));
+    assert_ne!(&Rgba([0, 0, 0, 255]), img.get_pixel(19, 0));
+    assert_ne!(&Rgba([0, 0, 0, 255]), img.get_pixel(20, 30));
+}
+
+#[test]
+fn test_render_hex_fog() {
+    let mut model = Model::default();
+    for cmd in commands::parse(
+        "battlemap --grid=hex-pointy --cell-size=80 --columns=3 --rows=2\nshadow A1".into(),
+    ) {
+        model.apply(&cmd.unwrap()).unwrap();
+    }
+    let (img, _) = render(&model, View::Player);
+    // only the hex is hidden, not the corners of its bounding box
+    assert_eq!(&Rgba([0, 0, 0, 255]), img.get_pixel(40, 40));
+    assert_ne!(&Rgba([0, 0, 0, 255]), img.get_pixel(3, 3));
+}
+
+#[test]
+fn test_render_missing_images() {
+    let mut model = Model::default();
+    for cmd in commands::parse(
+        "battlemap --url=./missing.jpg --columns=3 --rows=2\ntoken bob --image=./missing.png".into(),
+    ) {
+        model.apply(&cmd.unwrap()).unwrap();
+    }
+    let (img, warnings) = render(&model, View::Dm);
+    assert_eq!(raster::battlemap_placeholder(3, 2).dimensions(), img.dimensions());
+    assert_eq!(2, warnings.len());
+    assert!(warnings[0].starts_with("Can't load image ./missing.jpg"));
+    assert!(warnings[1].starts_with("Can't load image ./missing.png"));
+}
+
+#[test]
+fn test_render_file_skips_bad_lines() {
+    let dir = std::env::temp_dir().join(format!("dnd-token-pusher-render-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let game = dir.join("typo.game");
+    std::fs::write(&game, "battlemap --columns=3 --rows=2\ntokn bob\nshadow A1\n").unwrap();
+
+    let output = dir.join("typo.png");
+    render_file(&game, &output, View::Player).unwrap();
+    let img = raster::load_image(output.to_str().unwrap()).unwrap();
+    // the line after the typo still hides A1
+    assert_eq!(&Rgba([0, 0, 0, 255]), img.get_pixel(3, 3));
+    assert!(render_file(&game, &dir.join("missing/typo.png"), View::Player).is_err());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
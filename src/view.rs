@@ -0,0 +1,215 @@
+use crate::assets::Assets;
+use crate::domain;
+use crate::raster;
+
+use image::RgbaImage;
+use std::collections::HashMap;
+use tetra::graphics::{self, Color, DrawParams, Texture};
+use tetra::math::Vec2;
+use tetra::Context;
+
+const FOG_PLAYER_COL: Color = Color::BLACK;
+const FOG_DM_COL: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+const BOX_COL: Color = Color::rgba(1.0, 0.0, 0.0, 0.4);
+
+/// Renders a `domain::Model`. Owns every GPU resource and caches them, so the
+/// model itself stays free of tetra.
+pub struct View {
+    battlemap: Option<Battlemap>,
+    assets: Assets,
+    placeholders: HashMap<String, Texture>,
+    pixel: Texture,
+}
+
+/// GPU resources for the battlemap the model had when they were built.
+struct Battlemap {
+    source: domain::Battlemap,
+    texture: Texture,
+    cells: domain::Grid,
+    grid: Texture, // edges and coordinate labels, see `raster::grid_overlay`
+    cell_mask: Texture, // the shape of a single cell, see `raster::cell_mask`
+}
+
+impl View {
+    pub fn new(ctx: &mut Context) -> tetra::Result<View> {
+        Ok(View {
+            battlemap: None,
+            assets: Assets::new(),
+            placeholders: HashMap::new(),
+            pixel: Texture::from_rgba(ctx, 1, 1, &[255, 255, 255, 255])?,
+        })
+    }
+
+    /// Assets that failed to load since the last call. They are drawn as
+    /// placeholders instead.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        self.assets.take_errors()
+    }
+
+    /// Map a point in world (battlemap pixel) space to feet from the top left
+    /// corner of A1, if it is on the battlemap.
+    pub fn feet_at(&self, point: Vec2<f32>) -> Option<(f32, f32)> {
+        self.cell_at(point)?;
+        let bm = self.battlemap.as_ref()?;
+        Some(bm.cells.feet_at((point.x, point.y)))
+    }
+
+    /// Map a point in world (battlemap pixel) space to the cell under it.
+    pub fn cell_at(&self, point: Vec2<f32>) -> Option<(i32, i32)> {
+        let bm = self.battlemap.as_ref()?;
+        let cell = bm.cells.cell_at((point.x, point.y));
+        if bm.source.contains(cell) {
+            Some(cell)
+        } else {
+            None
+        }
+    }
+
+    pub fn render(&mut self, ctx: &mut Context, model: &domain::Model, dm_mode: bool) -> tetra::Result {
+        let reloaded = self.assets.reload_changed(ctx)?;
+        if let Some(bm) = &self.battlemap {
+            if bm.source.image_path.as_ref().is_some_and(|p| reloaded.contains(p)) {
+                self.battlemap = None;
+            }
+        }
+        self.sync_battlemap(ctx, &model.battlemap)?;
+
+        let cells = self.battlemap.as_ref().unwrap().cells;
+        self.battlemap.as_ref().unwrap().render(ctx);
+
+        for token in model.tokens.values() {
+            if !dm_mode && model.fog.is_hidden(token.position()) {
+                continue;
+            }
+            let texture = match &token.image {
+                Some(path) => self.assets.texture(ctx, path)?,
+                None => None,
+            };
+            let texture = match texture {
+                Some(t) => t,
+                None => {
+                    let key = format!("{}:{}", token.id, token.name);
+                    let img = || raster::token_placeholder(&token.id, &token.name);
+                    self.placeholder(ctx, key, img)?
+                }
+            };
+            render_token(ctx, &texture, token, cells);
+        }
+
+        self.render_fog(ctx, model, dm_mode);
+        Ok(())
+    }
+
+    /// Rebuild the battlemap resources when the model's battlemap changed.
+    fn sync_battlemap(&mut self, ctx: &mut Context, battlemap: &domain::Battlemap) -> tetra::Result {
+        let up_to_date = self
+            .battlemap
+            .as_ref()
+            .is_some_and(|bm| bm.source == *battlemap);
+        if up_to_date {
+            return Ok(());
+        }
+        let texture = match &battlemap.image_path {
+            Some(path) => self.assets.texture(ctx, path)?,
+            None => None,
+        };
+        let texture = match texture {
+            Some(t) => t,
+            None => {
+                let key = format!("battlemap:{}x{}", battlemap.columns, battlemap.rows);
+                let img = || raster::battlemap_placeholder(battlemap.columns, battlemap.rows);
+                self.placeholder(ctx, key, img)?
+            }
+        };
+        self.battlemap = Some(Battlemap::new(ctx, battlemap.clone(), texture)?);
+        Ok(())
+    }
+
+    fn placeholder<F: FnOnce() -> RgbaImage>(
+        &mut self,
+        ctx: &mut Context,
+        key: String,
+        img: F,
+    ) -> tetra::Result<Texture> {
+        if let Some(t) = self.placeholders.get(&key) {
+            return Ok(t.clone());
+        }
+        let img = img();
+        let texture = Texture::from_rgba(ctx, img.width() as i32, img.height() as i32, &img)?;
+        self.placeholders.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    /// Highlight the box between two corners in world space, e.g. while it is
+    /// being dragged.
+    pub fn render_box(&self, ctx: &mut Context, a: Vec2<f32>, b: Vec2<f32>) {
+        graphics::draw(
+            ctx,
+            &self.pixel,
+            DrawParams::default()
+                .position(Vec2::new(a.x.min(b.x), a.y.min(b.y)))
+                .scale(Vec2::new((a.x - b.x).abs(), (a.y - b.y).abs()))
+                .color(BOX_COL),
+        );
+    }
+
+    fn render_fog(&self, ctx: &mut Context, model: &domain::Model, dm_mode: bool) {
+        let bm = match &self.battlemap {
+            Some(bm) => bm,
+            None => return,
+        };
+        let color = if dm_mode { FOG_DM_COL } else { FOG_PLAYER_COL };
+        for col in 0..model.battlemap.columns {
+            for row in 0..model.battlemap.rows {
+                if model.fog.is_hidden((col, row)) {
+                    let (x, y) = bm.cells.origin((col, row));
+                    graphics::draw(
+                        ctx,
+                        &bm.cell_mask,
+                        DrawParams::default()
+                            .position(Vec2::new(x as f32, y as f32))
+                            .color(color),
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Battlemap {
+    fn new(ctx: &mut Context, source: domain::Battlemap, texture: Texture) -> tetra::Result<Self> {
+        let (width, height) = (texture.width(), texture.height());
+        let cells = source.grid((width as u32, height as u32));
+        let grid = raster::grid_overlay(width as u32, height as u32, cells, source.columns, source.rows);
+        let grid = Texture::from_rgba(ctx, width, height, &grid)?;
+        let mask = raster::cell_mask(cells);
+        let cell_mask = Texture::from_rgba(ctx, mask.width() as i32, mask.height() as i32, &mask)?;
+        Ok(Battlemap {
+            source,
+            texture,
+            cells,
+            grid,
+            cell_mask,
+        })
+    }
+
+    fn render(&self, ctx: &mut Context) {
+        // Now all drawing operations will be transformed:
+        graphics::draw(ctx, &self.texture, DrawParams::new());
+        graphics::draw(ctx, &self.grid, DrawParams::new());
+    }
+}
+
+fn render_token(ctx: &mut Context, texture: &Texture, token: &domain::Token, cells: domain::Grid) {
+    let (x, y) = cells.pixel(token);
+    let (tile_w, tile_h) = cells.cell;
+
+    let w = texture.width() as f32;
+    let h = texture.height() as f32;
+
+    let uniscale = (tile_w as f32 / w).min(tile_h as f32 / h);
+    let scale = Vec2::new(uniscale, uniscale);
+    // centred, so tokens sit in the middle of hexes too
+    let pos = Vec2::new(x - w * uniscale / 2.0, y - h * uniscale / 2.0);
+    graphics::draw(ctx, texture, DrawParams::default().position(pos).scale(scale));
+}